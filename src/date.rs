@@ -0,0 +1,199 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A calendar date, stored as days since 1970-01-01 and written to JSON as "YYYY-MM-DD"
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    days: i64,
+}
+
+impl Date {
+    pub fn from_ymd(year: i64, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Date {
+            days: days_from_civil(year, month, day),
+        })
+    }
+
+    // Accepts ISO dates such as 2024-05-01
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = s.trim().split('-').collect();
+        let invalid = || format!("Invalid date '{}', expected YYYY-MM-DD", s.trim());
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let year = parts[0].parse().map_err(|_| invalid())?;
+        let month = parts[1].parse().map_err(|_| invalid())?;
+        let day = parts[2].parse().map_err(|_| invalid())?;
+        Date::from_ymd(year, month, day).ok_or_else(invalid)
+    }
+
+    // Current date in UTC
+    pub fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Date {
+            days: secs.div_euclid(86_400),
+        }
+    }
+
+    pub fn add_days(self, days: i64) -> Self {
+        Date {
+            days: self.days + days,
+        }
+    }
+
+    // Positive when `later` comes after `self`
+    pub fn days_until(self, later: Date) -> i64 {
+        later.days - self.days
+    }
+}
+
+// Current UTC time as an ISO 8601 timestamp such as 2024-05-01T10:00:00Z
+pub fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let time_of_day = secs.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        Date {
+            days: secs.div_euclid(86_400)
+        },
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.days);
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Date::parse(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's days_from_civil: proleptic Gregorian date to days since the epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Inverse of days_from_civil
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> Date {
+        Date::parse(s).unwrap()
+    }
+
+    #[test]
+    fn epoch_is_day_zero_and_conversions_round_trip() {
+        assert_eq!(Date::from_ymd(1970, 1, 1), Some(Date { days: 0 }));
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in (-800_000..800_000).step_by(97) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn leap_days_follow_the_gregorian_rules() {
+        assert!(Date::from_ymd(2000, 2, 29).is_some());
+        assert!(Date::from_ymd(2024, 2, 29).is_some());
+        assert!(Date::from_ymd(2100, 2, 29).is_none());
+        assert!(Date::from_ymd(2023, 2, 29).is_none());
+        assert_eq!(date("2000-02-28").add_days(1), date("2000-02-29"));
+        assert_eq!(date("2100-02-28").add_days(1), date("2100-03-01"));
+    }
+
+    #[test]
+    fn arithmetic_crosses_year_boundaries() {
+        assert_eq!(date("1999-12-31").add_days(1), date("2000-01-01"));
+        assert_eq!(date("2024-01-01").add_days(-1), date("2023-12-31"));
+        assert_eq!(date("2023-12-31").days_until(date("2024-01-01")), 1);
+        assert_eq!(date("2024-01-01").days_until(date("2025-01-01")), 366);
+        assert_eq!(date("2025-01-01").days_until(date("2024-01-01")), -366);
+        assert_eq!(date("2024-12-25").add_days(14).to_string(), "2025-01-08");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_dates() {
+        assert_eq!(date(" 2024-05-01 ").to_string(), "2024-05-01");
+        for input in [
+            "",
+            "abc",
+            "2024-05",
+            "2024-05-01-01",
+            "2024/05/01",
+            "2024-1a-01",
+            "2024-00-10",
+            "2024-13-01",
+            "2024-04-31",
+            "2024-01-00",
+        ] {
+            assert!(Date::parse(input).is_err(), "{:?} should be rejected", input);
+        }
+    }
+
+    #[test]
+    fn serde_round_trips_through_iso_strings() {
+        let due = date("2024-05-01");
+        let json = serde_json::to_string(&due).unwrap();
+        assert_eq!(json, "\"2024-05-01\"");
+        assert_eq!(serde_json::from_str::<Date>(&json).unwrap(), due);
+        assert!(serde_json::from_str::<Date>("\"2024-02-30\"").is_err());
+    }
+}
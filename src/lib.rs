@@ -0,0 +1,1975 @@
+mod date;
+
+pub use date::Date;
+
+use date::utc_timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+
+pub const LOAN_PERIOD_DAYS: i64 = 14;
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct Book {
+    pub id: u32,
+    pub title: String,
+    pub author: String,
+    #[serde(default)]
+    pub total_copies: u32,
+    #[serde(default)]
+    pub available_copies: u32,
+    // Files written before copies were tracked store this flag instead; see load_from_file
+    #[serde(default, rename = "is_issued", skip_serializing)]
+    legacy_is_issued: Option<bool>,
+    // Free-form audience label such as "Children", "YA" or "Adult"
+    #[serde(default)]
+    pub age_rating: Option<String>,
+    // Staff member who catalogued the book
+    #[serde(default)]
+    pub added_by: Option<String>,
+    #[serde(default)]
+    pub demo: bool,
+    // IDs of users waiting for a copy, first in line first
+    #[serde(default)]
+    pub reservations: Vec<u32>,
+}
+
+impl Book {
+    pub fn is_available(&self) -> bool {
+        self.available_copies > 0
+    }
+
+    pub fn copies_out(&self) -> u32 {
+        self.total_copies.saturating_sub(self.available_copies)
+    }
+
+    // Puts a returned copy back on the shelf. Stale borrowed entries must not push the
+    // count past the copies owned.
+    fn check_in(&mut self) {
+        if self.available_copies < self.total_copies {
+            self.available_copies += 1;
+        }
+    }
+}
+
+// One book on loan to a user. Loans from before due dates were tracked have no due date.
+#[derive(Serialize, Deserialize)]
+#[serde(from = "StoredBorrow")]
+pub struct BorrowRecord {
+    pub book_id: u32,
+    pub due_date: Option<Date>,
+}
+
+// Older files store borrowed_books as plain book IDs
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredBorrow {
+    Id(u32),
+    Record { book_id: u32, due_date: Option<Date> },
+}
+
+impl From<StoredBorrow> for BorrowRecord {
+    fn from(stored: StoredBorrow) -> Self {
+        match stored {
+            StoredBorrow::Id(book_id) => BorrowRecord {
+                book_id,
+                due_date: None,
+            },
+            StoredBorrow::Record { book_id, due_date } => BorrowRecord { book_id, due_date },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+    pub borrowed_books: Vec<BorrowRecord>,
+    #[serde(default)]
+    pub suspended: bool,
+    #[serde(default)]
+    pub demo: bool,
+}
+
+impl User {
+    pub fn holds(&self, book_id: u32) -> bool {
+        self.borrowed_books.iter().any(|r| r.book_id == book_id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BannerMode {
+    #[default]
+    Minimal,
+    Full,
+    Off,
+}
+
+// Listing order for sorted_books; the stored order is never changed
+#[derive(Clone, Copy, Default)]
+pub enum BookSort {
+    #[default]
+    Id,
+    Title,
+    Author,
+}
+
+// Criteria for query_books; every field that is set must match
+#[derive(Default)]
+pub struct BookFilter {
+    pub title_contains: Option<String>,
+    pub author_contains: Option<String>,
+    pub available_only: bool,
+}
+
+impl BookFilter {
+    pub fn matches(&self, book: &Book) -> bool {
+        let contains = |field: &str, needle: &Option<String>| match needle {
+            Some(needle) => field.to_lowercase().contains(&needle.to_lowercase()),
+            None => true,
+        };
+        contains(&book.title, &self.title_contains)
+            && contains(&book.author, &self.author_contains)
+            && (!self.available_only || book.is_available())
+    }
+}
+
+// Counts for the statistics report, over the books and users currently visible
+#[derive(Debug, PartialEq)]
+pub struct LibraryStats {
+    pub books: usize,
+    pub books_issued: usize,
+    pub books_available: usize,
+    pub total_copies: u32,
+    pub copies_on_loan: u32,
+    pub users: usize,
+    // (name, loans) of the user holding the most books; the earliest registered wins a tie
+    pub most_borrowed: Option<(String, usize)>,
+}
+
+// Outcome of returning everything a user holds
+#[derive(Debug, PartialEq)]
+pub struct ReturnedBooks {
+    // (title, reserving user the copy was passed on to) for each book checked back in
+    pub returned: Vec<(String, Option<String>)>,
+    // IDs of loans dropped because their book no longer exists
+    pub unknown: Vec<u32>,
+}
+
+// Why a change to the library was refused. User names are reported as the caller gave them.
+#[derive(Debug, PartialEq)]
+pub enum LibraryError {
+    BookNotFound(u32),
+    UserNotFound(String),
+    UserExists(String),
+    // A book with the same title and author is already catalogued under this ID
+    DuplicateBook(u32),
+    InvalidCopies,
+    // Adding the copies would overflow the book's copy counts
+    TooManyCopies(u32),
+    BookUnavailable { id: u32, title: String },
+    UserSuspended(String),
+    AlreadySuspended(String),
+    NotSuspended(String),
+    AlreadyHolds { user: String, title: String },
+    AlreadyReserved { user: String, title: String },
+    // Reservations are only for books with every copy out
+    CopiesAvailable { id: u32, title: String },
+    BorrowLimitReached { user: String, limit: u32 },
+    NotBorrowed { id: u32, title: String, user: String },
+    CopiesOnLoan(String),
+    StillListed { id: u32, user: String },
+    NotSameTitle(u32, u32),
+    EmptyQuery,
+    BlankField(&'static str),
+    // Every user listing the book has a copy on loan, so no entry can be called stale
+    HoldsConsistent(u32),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::BookNotFound(id) => write!(f, "No book found with ID {}.", id),
+            LibraryError::UserNotFound(user) => write!(f, "No user found with name '{}'.", user),
+            LibraryError::UserExists(user) => write!(f, "User '{}' already exists!", user),
+            LibraryError::DuplicateBook(id) => {
+                write!(f, "A book with that title and author already exists (ID {}).", id)
+            }
+            LibraryError::InvalidCopies => write!(f, "Number of copies must be at least 1!"),
+            LibraryError::TooManyCopies(id) => write!(f, "Book ID {} cannot hold that many copies.", id),
+            LibraryError::BookUnavailable { id, title } => {
+                write!(f, "All copies of '{}' (ID {}) are already issued.", title, id)
+            }
+            LibraryError::UserSuspended(user) => {
+                write!(f, "User '{}' is suspended and cannot borrow books.", user)
+            }
+            LibraryError::AlreadySuspended(user) => write!(f, "User '{}' is already suspended.", user),
+            LibraryError::NotSuspended(user) => write!(f, "User '{}' is not suspended.", user),
+            LibraryError::AlreadyHolds { user, title } => {
+                write!(f, "User '{}' already has a copy of '{}'.", user, title)
+            }
+            LibraryError::AlreadyReserved { user, title } => {
+                write!(f, "User '{}' has already reserved '{}'.", user, title)
+            }
+            LibraryError::CopiesAvailable { id, title } => {
+                write!(f, "'{}' (ID {}) has copies available; issue it instead.", title, id)
+            }
+            LibraryError::BorrowLimitReached { user, limit } => {
+                write!(f, "User '{}' has reached the borrowing limit of {}.", user, limit)
+            }
+            LibraryError::NotBorrowed { id, title, user } => {
+                write!(f, "Book '{}' (ID {}) is not borrowed by user '{}'.", title, id, user)
+            }
+            LibraryError::CopiesOnLoan(title) => {
+                write!(f, "Book '{}' has copies currently issued. Return them first!", title)
+            }
+            LibraryError::StillListed { id, user } => write!(
+                f,
+                "User '{}' still lists book ID {} as borrowed. Resolve this first!",
+                user, id
+            ),
+            LibraryError::NotSameTitle(from, to) => {
+                write!(f, "Books {} and {} are not copies of the same title.", from, to)
+            }
+            LibraryError::EmptyQuery => write!(f, "Search term cannot be empty!"),
+            LibraryError::BlankField(field) => write!(f, "{} cannot be empty!", field),
+            LibraryError::HoldsConsistent(id) => {
+                write!(f, "Every user listing book ID {} has a copy on loan.", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+#[derive(Serialize, Deserialize)]
+pub struct Library {
+    pub books: Vec<Book>,
+    pub users: Vec<User>,
+    // Token overlap (0.0–1.0) above which a new title is reported as similar to an existing one
+    #[serde(default = "default_similarity_threshold")]
+    pub similarity_threshold: f64,
+    #[serde(default)]
+    pub startup_banner: BannerMode,
+    // Most books a user may hold at once; lowering it only blocks new issues
+    #[serde(default = "default_max_borrowed")]
+    max_borrowed: u32,
+    // IDs only ever grow, so deleted records never have their ID handed out again.
+    // Files written before these existed load as 0 and are reconciled in load_from_file.
+    #[serde(default)]
+    next_book_id: u32,
+    #[serde(default)]
+    next_user_id: u32,
+    // Title/author token -> IDs of books containing it; rebuilt on load
+    #[serde(skip)]
+    search_index: BTreeMap<String, BTreeSet<u32>>,
+    // Session settings: mark new records as demo data / hide demo data from listings
+    #[serde(skip)]
+    pub demo_mode: bool,
+    #[serde(skip)]
+    pub exclude_demo: bool,
+    // Set by every successful change so callers can save once per operation
+    #[serde(skip)]
+    dirty: bool,
+    // Append-only record of issues, returns and additions; None disables it
+    #[serde(skip)]
+    pub log_file: Option<String>,
+}
+
+fn default_similarity_threshold() -> f64 {
+    0.6
+}
+
+fn default_max_borrowed() -> u32 {
+    5
+}
+
+// Quotes a CSV value when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Quotes a log value when it would otherwise break the space-separated key=value format
+fn log_field(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+// Single rule for matching user names, titles and authors: trimmed and case-insensitive.
+// Stored values keep the casing they were entered with.
+fn names_match(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+// Lowercased alphanumeric words, used to compare titles loosely
+fn title_tokens(title: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+fn book_tokens(book: &Book) -> Vec<String> {
+    let mut tokens = title_tokens(&book.title);
+    tokens.extend(title_tokens(&book.author));
+    tokens
+}
+
+// Jaccard similarity of the two titles' token sets
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let a = title_tokens(a);
+    let b = title_tokens(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let shared = a.iter().filter(|t| b.contains(t)).count();
+    let total = a.len() + b.len() - shared;
+    shared as f64 / total as f64
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Library::new()
+    }
+}
+
+impl Library {
+    pub fn new() -> Self {
+        Library {
+            books: Vec::new(),
+            users: Vec::new(),
+            similarity_threshold: default_similarity_threshold(),
+            startup_banner: BannerMode::default(),
+            max_borrowed: default_max_borrowed(),
+            next_book_id: 1,
+            next_user_id: 1,
+            search_index: BTreeMap::new(),
+            demo_mode: false,
+            exclude_demo: false,
+            dirty: false,
+            log_file: None,
+        }
+    }
+
+    pub fn load_from_file(filename: &str) -> Result<Self, String> {
+        if std::path::Path::new(filename).exists() {
+            let data = fs::read_to_string(filename).map_err(|e| format!("Failed to read file: {}", e))?;
+            let mut library: Library = serde_json::from_str(&data).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+            library.migrate_legacy_books();
+            library.clamp_available_copies();
+            library.reconcile_next_ids();
+            library.rebuild_search_index();
+            Ok(library)
+        } else {
+            Ok(Library::new())
+        }
+    }
+
+    // A single is_issued flag becomes one copy that is either on loan or on the shelf
+    fn migrate_legacy_books(&mut self) {
+        for book in self.books.iter_mut() {
+            if let Some(is_issued) = book.legacy_is_issued.take() {
+                book.total_copies = 1;
+                book.available_copies = if is_issued { 0 } else { 1 };
+            }
+        }
+    }
+
+    // A hand-edited file can claim more copies on the shelf than the library owns
+    fn clamp_available_copies(&mut self) {
+        for book in self.books.iter_mut() {
+            book.available_copies = book.available_copies.min(book.total_copies);
+        }
+    }
+
+    fn reconcile_next_ids(&mut self) {
+        let max_book_id = self.books.iter().map(|b| b.id).max().unwrap_or(0);
+        let max_user_id = self.users.iter().map(|u| u.id).max().unwrap_or(0);
+        self.next_book_id = self.next_book_id.max(max_book_id + 1);
+        self.next_user_id = self.next_user_id.max(max_user_id + 1);
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize to JSON: {}", e))?;
+        // Write the full state next to the target, then swap it in, so a crash mid-write
+        // never leaves a truncated data file behind
+        let temp_filename = format!("{}.tmp", filename);
+        if let Err(e) = fs::write(&temp_filename, data) {
+            let _ = fs::remove_file(&temp_filename);
+            return Err(format!("Failed to write file: {}", e));
+        }
+        if let Err(e) = fs::rename(&temp_filename, filename) {
+            let _ = fs::remove_file(&temp_filename);
+            return Err(format!("Failed to replace file: {}", e));
+        }
+        Ok(())
+    }
+
+    // Saves only when something changed since the last save
+    pub fn save_if_changed(&mut self, filename: &str) -> Result<(), String> {
+        if self.dirty {
+            self.save_to_file(filename)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    // Appends one line to the audit log. A failed write only warns: the change itself already happened.
+    fn log_event(&self, action: &str, details: &str) {
+        let Some(log_file) = &self.log_file else {
+            return;
+        };
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .and_then(|mut file| writeln!(file, "{} {} {}", utc_timestamp(), action, details));
+        if let Err(e) = result {
+            eprintln!("Warning: could not write to audit log {}: {}", log_file, e);
+        }
+    }
+
+    // The last `count` audit log lines, oldest first
+    pub fn recent_log_entries(&self, count: usize) -> Result<Vec<String>, String> {
+        let Some(log_file) = &self.log_file else {
+            return Ok(Vec::new());
+        };
+        let file = match fs::File::open(log_file) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read log: {}", e)),
+        };
+        let lines: Vec<String> = BufReader::new(file)
+            .lines()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to read log: {}", e))?;
+        let start = lines.len().saturating_sub(count);
+        Ok(lines[start..].to_vec())
+    }
+
+    pub fn max_borrowed(&self) -> u32 {
+        self.max_borrowed
+    }
+
+    pub fn set_max_borrowed(&mut self, limit: u32) {
+        self.max_borrowed = limit;
+        self.dirty = true;
+    }
+
+    pub fn book(&self, id: u32) -> Option<&Book> {
+        self.books.iter().find(|b| b.id == id)
+    }
+
+    pub fn user(&self, name: &str) -> Option<&User> {
+        self.users.iter().find(|u| names_match(&u.name, name))
+    }
+
+    pub fn visible_books(&self) -> impl Iterator<Item = &Book> {
+        self.books.iter().filter(|b| !(self.exclude_demo && b.demo))
+    }
+
+    pub fn visible_users(&self) -> impl Iterator<Item = &User> {
+        self.users.iter().filter(|u| !(self.exclude_demo && u.demo))
+    }
+
+    // Visible books in the requested order; title and author ignore case
+    pub fn sorted_books(&self, sort: BookSort) -> Vec<&Book> {
+        let mut books: Vec<&Book> = self.visible_books().collect();
+        match sort {
+            BookSort::Id => books.sort_by_key(|b| b.id),
+            BookSort::Title => books.sort_by_cached_key(|b| b.title.to_lowercase()),
+            BookSort::Author => books.sort_by_cached_key(|b| b.author.to_lowercase()),
+        }
+        books
+    }
+
+    pub fn stats(&self) -> LibraryStats {
+        let books: Vec<&Book> = self.visible_books().collect();
+        let mut most_borrowed: Option<&User> = None;
+        for user in self.visible_users() {
+            if user.borrowed_books.len() > most_borrowed.map_or(0, |t| t.borrowed_books.len()) {
+                most_borrowed = Some(user);
+            }
+        }
+        LibraryStats {
+            books: books.len(),
+            books_issued: books.iter().filter(|b| b.copies_out() > 0).count(),
+            books_available: books.iter().filter(|b| b.is_available()).count(),
+            total_copies: books.iter().map(|b| b.total_copies).sum(),
+            copies_on_loan: books.iter().map(|b| b.copies_out()).sum(),
+            users: self.visible_users().count(),
+            most_borrowed: most_borrowed.map(|u| (u.name.clone(), u.borrowed_books.len())),
+        }
+    }
+
+    // Deletes all demo books and users, returning how many of each were removed
+    pub fn purge_demo(&mut self) -> (usize, usize) {
+        let demo_ids: Vec<u32> = self.books.iter().filter(|b| b.demo).map(|b| b.id).collect();
+        let users_before = self.users.len();
+
+        self.books.retain(|b| !b.demo);
+        // Copies of real books lent to demo users go back on the shelf with them
+        let mut demo_user_ids = Vec::new();
+        for user in self.users.iter().filter(|u| u.demo) {
+            demo_user_ids.push(user.id);
+            for record in &user.borrowed_books {
+                if let Some(book) = self.books.iter_mut().find(|b| b.id == record.book_id) {
+                    book.check_in();
+                }
+            }
+        }
+        self.users.retain(|u| !u.demo);
+        for user in self.users.iter_mut() {
+            user.borrowed_books.retain(|r| !demo_ids.contains(&r.book_id));
+        }
+        for book in self.books.iter_mut() {
+            book.reservations.retain(|id| !demo_user_ids.contains(id));
+        }
+        self.rebuild_search_index();
+
+        let users_removed = users_before - self.users.len();
+        if !demo_ids.is_empty() || users_removed > 0 {
+            self.dirty = true;
+        }
+        (demo_ids.len(), users_removed)
+    }
+
+    pub fn export_books_csv(&self, path: &str) -> Result<(), String> {
+        // The byte order mark makes Excel read the file as UTF-8 rather than the local code page
+        let mut csv = String::from("\u{feff}id,title,author,status\r\n");
+        for book in self.visible_books() {
+            let status = format!("{}/{} available", book.available_copies, book.total_copies);
+            csv.push_str(&format!(
+                "{},{},{},{}\r\n",
+                book.id,
+                csv_field(&book.title),
+                csv_field(&book.author),
+                csv_field(&status)
+            ));
+        }
+        fs::write(path, csv).map_err(|e| format!("Failed to write file: {}", e))
+    }
+
+    // Text shown at startup, according to the configured banner mode
+    pub fn banner(&self) -> String {
+        match self.startup_banner {
+            BannerMode::Off => String::new(),
+            BannerMode::Minimal => format!(
+                "Library initialized with {} books and {} users",
+                self.visible_books().count(),
+                self.visible_users().count()
+            ),
+            BannerMode::Full => {
+                let books = self.visible_books().count();
+                let copies: u32 = self.visible_books().map(|b| b.total_copies).sum();
+                let on_loan: u32 = self.visible_books().map(|b| b.copies_out()).sum();
+                let overdue = self.overdue_books(Date::today()).len();
+                let users = self.visible_users().count();
+                let suspended = self.visible_users().filter(|u| u.suspended).count();
+                format!(
+                    "Library initialized\n  Books: {} ({} copies: {} on loan, {} overdue, {} available)\n  Users: {} ({} suspended)",
+                    books,
+                    copies,
+                    on_loan,
+                    overdue,
+                    copies - on_loan,
+                    users,
+                    suspended
+                )
+            }
+        }
+    }
+
+    // Catalogues a new book and returns its ID. An exact duplicate is left for the caller to resolve.
+    pub fn add_book(
+        &mut self,
+        title: String,
+        author: String,
+        copies: u32,
+        age_rating: Option<String>,
+        added_by: Option<String>,
+    ) -> Result<u32, LibraryError> {
+        if copies == 0 {
+            return Err(LibraryError::InvalidCopies);
+        }
+        if let Some(existing) = self.find_book(&title, &author) {
+            return Err(LibraryError::DuplicateBook(existing.id));
+        }
+
+        let id = self.next_book_id;
+        self.next_book_id += 1;
+        self.log_event(
+            "ADD_BOOK",
+            &format!(
+                "book={} title={} author={} copies={}",
+                id,
+                log_field(&title),
+                log_field(&author),
+                copies
+            ),
+        );
+        let book = Book {
+            id,
+            title,
+            author,
+            total_copies: copies,
+            available_copies: copies,
+            legacy_is_issued: None,
+            age_rating,
+            added_by,
+            demo: self.demo_mode,
+            reservations: Vec::new(),
+        };
+        self.index_book(&book);
+        self.books.push(book);
+        self.dirty = true;
+        Ok(id)
+    }
+
+    // Same title and author, ignoring case and surrounding whitespace
+    pub fn find_book(&self, title: &str, author: &str) -> Option<&Book> {
+        self.books
+            .iter()
+            .find(|b| names_match(&b.title, title) && names_match(&b.author, author))
+    }
+
+    // New copies go to waiting readers first; returns who was served, in queue order
+    pub fn add_copies(&mut self, id: u32, copies: u32) -> Result<Vec<String>, LibraryError> {
+        let book = self
+            .books
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+        if copies == 0 {
+            return Err(LibraryError::InvalidCopies);
+        }
+        let (Some(total), Some(available)) = (
+            book.total_copies.checked_add(copies),
+            book.available_copies.checked_add(copies),
+        ) else {
+            return Err(LibraryError::TooManyCopies(id));
+        };
+        book.total_copies = total;
+        book.available_copies = available;
+        self.log_event("ADD_COPIES", &format!("book={} copies={}", id, copies));
+        self.dirty = true;
+
+        let mut served = Vec::new();
+        for _ in 0..copies {
+            match self.issue_to_next_reservation(id) {
+                Some(name) => served.push(name),
+                None => break,
+            }
+        }
+        Ok(served)
+    }
+
+    // Only the given fields change; copies and loans are untouched
+    pub fn edit_book(
+        &mut self,
+        id: u32,
+        new_title: Option<String>,
+        new_author: Option<String>,
+    ) -> Result<(), LibraryError> {
+        let index = self
+            .books
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+
+        let title = match new_title.map(|t| t.trim().to_string()) {
+            Some(t) if t.is_empty() => return Err(LibraryError::BlankField("Title")),
+            Some(t) => t,
+            None => self.books[index].title.clone(),
+        };
+        let author = match new_author.map(|a| a.trim().to_string()) {
+            Some(a) if a.is_empty() => return Err(LibraryError::BlankField("Author")),
+            Some(a) => a,
+            None => self.books[index].author.clone(),
+        };
+        // Renaming into another record's title and author would recreate a duplicate
+        if let Some(existing) = self
+            .books
+            .iter()
+            .find(|b| b.id != id && names_match(&b.title, &title) && names_match(&b.author, &author))
+        {
+            return Err(LibraryError::DuplicateBook(existing.id));
+        }
+
+        let mut book = self.books.remove(index);
+        self.unindex_book(&book);
+        book.title = title;
+        book.author = author;
+        self.index_book(&book);
+        self.books.insert(index, book);
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Returns the removed book
+    pub fn remove_book(&mut self, id: u32) -> Result<Book, LibraryError> {
+        let index = self
+            .books
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+
+        if self.books[index].copies_out() > 0 {
+            return Err(LibraryError::CopiesOnLoan(self.books[index].title.clone()));
+        }
+
+        // Never leave a dangling ID in a user's borrowed list
+        if let Some(user) = self.users.iter().find(|u| u.holds(id)) {
+            return Err(LibraryError::StillListed {
+                id,
+                user: user.name.clone(),
+            });
+        }
+
+        let book = self.books.remove(index);
+        self.unindex_book(&book);
+        self.dirty = true;
+        Ok(book)
+    }
+
+    fn index_book(&mut self, book: &Book) {
+        for token in book_tokens(book) {
+            self.search_index.entry(token).or_default().insert(book.id);
+        }
+    }
+
+    fn unindex_book(&mut self, book: &Book) {
+        for token in book_tokens(book) {
+            if let Some(ids) = self.search_index.get_mut(&token) {
+                ids.remove(&book.id);
+                if ids.is_empty() {
+                    self.search_index.remove(&token);
+                }
+            }
+        }
+    }
+
+    fn rebuild_search_index(&mut self) {
+        let mut index: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
+        for book in &self.books {
+            for token in book_tokens(book) {
+                index.entry(token).or_default().insert(book.id);
+            }
+        }
+        self.search_index = index;
+    }
+
+    // Every word of the query must be a prefix of some title or author word
+    pub fn index_search(&self, query: &str) -> Vec<&Book> {
+        let mut matches: Option<BTreeSet<u32>> = None;
+        for prefix in title_tokens(query) {
+            let ids: BTreeSet<u32> = self
+                .search_index
+                .range(prefix.clone()..)
+                .take_while(|(token, _)| token.starts_with(&prefix))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect();
+            matches = Some(match matches {
+                Some(previous) => previous.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+
+        match matches {
+            Some(ids) => self.visible_books().filter(|b| ids.contains(&b.id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Books grouped by who added them; None collects books with no recorded operator
+    pub fn acquisitions_by_operator(&self) -> BTreeMap<Option<&str>, Vec<&Book>> {
+        let mut report: BTreeMap<Option<&str>, Vec<&Book>> = BTreeMap::new();
+        for book in self.visible_books() {
+            report.entry(book.added_by.as_deref()).or_default().push(book);
+        }
+        report
+    }
+
+    pub fn books_for_age(&self, rating: &str) -> Vec<&Book> {
+        self.visible_books()
+            .filter(|b| {
+                b.age_rating
+                    .as_deref()
+                    .is_some_and(|r| r.eq_ignore_ascii_case(rating))
+            })
+            .collect()
+    }
+
+    pub fn similar_titles(&self, title: &str, threshold: f64) -> Vec<&Book> {
+        self.books
+            .iter()
+            .filter(|b| title_similarity(&b.title, title) >= threshold)
+            .collect()
+    }
+
+    // Case-insensitive substring match on title or author. A blank query is an error, so
+    // callers can tell "no query" apart from "no matches".
+    pub fn search_books(&self, query: &str) -> Result<Vec<&Book>, LibraryError> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Err(LibraryError::EmptyQuery);
+        }
+        Ok(self
+            .visible_books()
+            .filter(|b| b.title.to_lowercase().contains(&query) || b.author.to_lowercase().contains(&query))
+            .collect())
+    }
+
+    pub fn query_books(&self, filter: &BookFilter) -> Vec<&Book> {
+        self.visible_books().filter(|b| filter.matches(b)).collect()
+    }
+
+    // Registers a user under the trimmed name and returns their ID
+    pub fn add_user(&mut self, name: String) -> Result<u32, LibraryError> {
+        let name = name.trim().to_string();
+        if self.users.iter().any(|u| names_match(&u.name, &name)) {
+            return Err(LibraryError::UserExists(name));
+        }
+
+        let id = self.next_user_id;
+        self.next_user_id += 1;
+        self.log_event("ADD_USER", &format!("user={}", log_field(&name)));
+        self.users.push(User {
+            id,
+            name,
+            borrowed_books: Vec::new(),
+            suspended: false,
+            demo: self.demo_mode,
+        });
+        self.dirty = true;
+        Ok(id)
+    }
+
+    // Hand the user a different physical copy of the same title in place of the one they hold
+    pub fn swap_copy(&mut self, user: &str, from_book_id: u32, to_book_id: u32) -> Result<(), LibraryError> {
+        let user_index = self
+            .users
+            .iter()
+            .position(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+        let from_index = self
+            .books
+            .iter()
+            .position(|b| b.id == from_book_id)
+            .ok_or(LibraryError::BookNotFound(from_book_id))?;
+        let to_index = self
+            .books
+            .iter()
+            .position(|b| b.id == to_book_id)
+            .ok_or(LibraryError::BookNotFound(to_book_id))?;
+
+        let from = &self.books[from_index];
+        let to = &self.books[to_index];
+        if from_book_id == to_book_id || !names_match(&from.title, &to.title) || !names_match(&from.author, &to.author) {
+            return Err(LibraryError::NotSameTitle(from_book_id, to_book_id));
+        }
+        if !self.users[user_index].holds(from_book_id) {
+            return Err(LibraryError::NotBorrowed {
+                id: from_book_id,
+                title: from.title.clone(),
+                user: user.to_string(),
+            });
+        }
+        if !to.is_available() {
+            return Err(LibraryError::BookUnavailable {
+                id: to_book_id,
+                title: to.title.clone(),
+            });
+        }
+        if self.users[user_index].holds(to_book_id) {
+            return Err(LibraryError::AlreadyHolds {
+                user: user.to_string(),
+                title: to.title.clone(),
+            });
+        }
+
+        self.books[from_index].available_copies += 1;
+        self.books[to_index].available_copies -= 1;
+        // The new copy takes over the existing loan, due date included
+        for record in self.users[user_index].borrowed_books.iter_mut() {
+            if record.book_id == from_book_id {
+                record.book_id = to_book_id;
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn suspend_user(&mut self, name: &str) -> Result<(), LibraryError> {
+        match self.users.iter_mut().find(|u| names_match(&u.name, name)) {
+            Some(user) if user.suspended => Err(LibraryError::AlreadySuspended(name.to_string())),
+            Some(user) => {
+                user.suspended = true;
+                self.dirty = true;
+                Ok(())
+            }
+            None => Err(LibraryError::UserNotFound(name.to_string())),
+        }
+    }
+
+    pub fn reinstate_user(&mut self, name: &str) -> Result<(), LibraryError> {
+        match self.users.iter_mut().find(|u| names_match(&u.name, name)) {
+            Some(user) if !user.suspended => Err(LibraryError::NotSuspended(name.to_string())),
+            Some(user) => {
+                user.suspended = false;
+                self.dirty = true;
+                Ok(())
+            }
+            None => Err(LibraryError::UserNotFound(name.to_string())),
+        }
+    }
+
+    // (user, book id) for books listed as borrowed while no copy is out at all; every such
+    // entry is certainly stale
+    pub fn ghost_holds(&self) -> Vec<(String, u32)> {
+        let mut ghosts = Vec::new();
+        for book in self.books.iter().filter(|b| b.copies_out() == 0) {
+            for user in self.users.iter().filter(|u| u.holds(book.id)) {
+                ghosts.push((user.name.clone(), book.id));
+            }
+        }
+        ghosts
+    }
+
+    // (book id, every holder) for books listed by more users than it has copies out. The
+    // data can't tell which entries are stale, so the operator must pick them.
+    pub fn ambiguous_holds(&self) -> Vec<(u32, Vec<String>)> {
+        let mut ambiguous = Vec::new();
+        for book in self.books.iter().filter(|b| b.copies_out() > 0) {
+            let holders: Vec<String> = self
+                .users
+                .iter()
+                .filter(|u| u.holds(book.id))
+                .map(|u| u.name.clone())
+                .collect();
+            if holders.len() > book.copies_out() as usize {
+                ambiguous.push((book.id, holders));
+            }
+        }
+        ambiguous
+    }
+
+    // Drops one user's stale entry for a book that has more holders than copies out.
+    // Copy counts are left alone: the entry never had a copy behind it.
+    pub fn remove_stale_hold(&mut self, user: &str, book_id: u32) -> Result<(), LibraryError> {
+        let book = self.book(book_id).ok_or(LibraryError::BookNotFound(book_id))?;
+        let holders = self.users.iter().filter(|u| u.holds(book_id)).count();
+        if holders <= book.copies_out() as usize {
+            return Err(LibraryError::HoldsConsistent(book_id));
+        }
+        let title = book.title.clone();
+        let user_record = self
+            .users
+            .iter_mut()
+            .find(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+        if !user_record.holds(book_id) {
+            return Err(LibraryError::NotBorrowed {
+                id: book_id,
+                title,
+                user: user.to_string(),
+            });
+        }
+        user_record.borrowed_books.retain(|r| r.book_id != book_id);
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn repair_ghost_holds(&mut self) -> usize {
+        let ghosts = self.ghost_holds();
+        for (name, book_id) in &ghosts {
+            for user_record in self.users.iter_mut() {
+                if &user_record.name == name {
+                    user_record.borrowed_books.retain(|r| r.book_id != *book_id);
+                }
+            }
+        }
+        if !ghosts.is_empty() {
+            self.dirty = true;
+        }
+        ghosts.len()
+    }
+
+    // Returns every book the user holds. Loans of books that no longer exist are dropped
+    // and reported separately.
+    pub fn return_all_for_user(&mut self, user: &str) -> Result<ReturnedBooks, LibraryError> {
+        let user_record = self
+            .users
+            .iter_mut()
+            .find(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+
+        if !user_record.borrowed_books.is_empty() {
+            self.dirty = true;
+        }
+        let user_name = user_record.name.clone();
+        let mut returned = Vec::new();
+        let mut unknown = Vec::new();
+        for record in user_record.borrowed_books.drain(..) {
+            match self.books.iter_mut().find(|b| b.id == record.book_id) {
+                Some(book) => {
+                    book.check_in();
+                    returned.push((book.title.clone(), book.id));
+                }
+                None => unknown.push(record.book_id),
+            }
+        }
+
+        let mut results = Vec::new();
+        for (title, id) in returned {
+            self.log_event("RETURN", &format!("book={} user={}", id, log_field(&user_name)));
+            let passed_to = self.issue_to_next_reservation(id);
+            results.push((title, passed_to));
+        }
+        Ok(ReturnedBooks {
+            returned: results,
+            unknown,
+        })
+    }
+
+    // (user name, book id, due date) for every loan due before `today`
+    pub fn overdue_books(&self, today: Date) -> Vec<(String, u32, Date)> {
+        let mut overdue = Vec::new();
+        let hidden_books: Vec<u32> = self
+            .books
+            .iter()
+            .filter(|b| self.exclude_demo && b.demo)
+            .map(|b| b.id)
+            .collect();
+        for user in self.visible_users() {
+            for record in user.borrowed_books.iter().filter(|r| !hidden_books.contains(&r.book_id)) {
+                if let Some(due_date) = record.due_date.filter(|&d| d < today) {
+                    overdue.push((user.name.clone(), record.book_id, due_date));
+                }
+            }
+        }
+        overdue.sort_by_key(|&(_, _, due_date)| due_date);
+        overdue
+    }
+
+    // Lends one copy to the user and returns the due date
+    pub fn issue_book_by_id(&mut self, id: u32, user: &str) -> Result<Date, LibraryError> {
+        // Check if user exists
+        if !self.users.iter().any(|u| names_match(&u.name, user)) {
+            return Err(LibraryError::UserNotFound(user.to_string()));
+        }
+
+        // Suspended users may still return books, but not borrow new ones
+        if self.users.iter().any(|u| names_match(&u.name, user) && u.suspended) {
+            return Err(LibraryError::UserSuspended(user.to_string()));
+        }
+
+        // Check if book exists and is available
+        let book = self
+            .books
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+        if !book.is_available() {
+            return Err(LibraryError::BookUnavailable {
+                id,
+                title: book.title.clone(),
+            });
+        }
+        let max_borrowed = self.max_borrowed;
+
+        // Update user's borrowed_books
+        let user_record = self
+            .users
+            .iter_mut()
+            .find(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+        if user_record.holds(id) {
+            return Err(LibraryError::AlreadyHolds {
+                user: user.to_string(),
+                title: book.title.clone(),
+            });
+        }
+        if user_record.borrowed_books.len() >= max_borrowed as usize {
+            return Err(LibraryError::BorrowLimitReached {
+                user: user.to_string(),
+                limit: max_borrowed,
+            });
+        }
+
+        let due_date = Date::today().add_days(LOAN_PERIOD_DAYS);
+        book.available_copies -= 1;
+        // A reservation is fulfilled however the user ends up with a copy
+        book.reservations.retain(|&u| u != user_record.id);
+        user_record.borrowed_books.push(BorrowRecord {
+            book_id: id,
+            due_date: Some(due_date),
+        });
+        self.dirty = true;
+        let details = format!("book={} user={} due={}", id, log_field(&user_record.name), due_date);
+        self.log_event("ISSUE", &details);
+        Ok(due_date)
+    }
+
+    // Checks the copy back in. If anyone reserved the book it goes straight to them, and the
+    // name of that user is returned.
+    pub fn return_book_by_id(&mut self, id: u32, user: &str) -> Result<Option<String>, LibraryError> {
+        // Check if user exists
+        let user_record = self
+            .users
+            .iter_mut()
+            .find(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+
+        // Check if book exists
+        let book = self
+            .books
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+
+        // Check if user borrowed the book
+        let index = user_record
+            .borrowed_books
+            .iter()
+            .position(|r| r.book_id == id)
+            .ok_or_else(|| LibraryError::NotBorrowed {
+                id,
+                title: book.title.clone(),
+                user: user.to_string(),
+            })?;
+
+        user_record.borrowed_books.remove(index);
+        book.check_in();
+        self.dirty = true;
+        let details = format!("book={} user={}", id, log_field(&user_record.name));
+        self.log_event("RETURN", &details);
+        Ok(self.issue_to_next_reservation(id))
+    }
+
+    // Joins the queue for a book whose copies are all out; returns the user's place in line
+    pub fn reserve_book(&mut self, id: u32, user: &str) -> Result<usize, LibraryError> {
+        let user_record = self
+            .users
+            .iter()
+            .find(|u| names_match(&u.name, user))
+            .ok_or_else(|| LibraryError::UserNotFound(user.to_string()))?;
+        if user_record.suspended {
+            return Err(LibraryError::UserSuspended(user.to_string()));
+        }
+        let book = self
+            .books
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or(LibraryError::BookNotFound(id))?;
+
+        if user_record.holds(id) {
+            return Err(LibraryError::AlreadyHolds {
+                user: user.to_string(),
+                title: book.title.clone(),
+            });
+        }
+        if book.reservations.contains(&user_record.id) {
+            return Err(LibraryError::AlreadyReserved {
+                user: user.to_string(),
+                title: book.title.clone(),
+            });
+        }
+        if book.is_available() {
+            return Err(LibraryError::CopiesAvailable {
+                id,
+                title: book.title.clone(),
+            });
+        }
+
+        book.reservations.push(user_record.id);
+        let position = book.reservations.len();
+        self.dirty = true;
+        let details = format!("book={} user={}", id, log_field(&user_record.name));
+        self.log_event("RESERVE", &details);
+        Ok(position)
+    }
+
+    // Issues a copy that just came free to the first reserving user who may borrow it. Anyone
+    // who can't right now (suspended or at the borrowing limit) is skipped but keeps their
+    // place; only users who are no longer registered are dropped from the queue.
+    fn issue_to_next_reservation(&mut self, id: u32) -> Option<String> {
+        let book = self.books.iter_mut().find(|b| b.id == id)?;
+        let users = &self.users;
+        let queued = book.reservations.len();
+        book.reservations.retain(|&user_id| users.iter().any(|u| u.id == user_id));
+        if book.reservations.len() != queued {
+            self.dirty = true;
+        }
+
+        for user_id in book.reservations.clone() {
+            let Some(name) = self.users.iter().find(|u| u.id == user_id).map(|u| u.name.clone()) else {
+                continue;
+            };
+            if self.issue_book_by_id(id, &name).is_ok() {
+                return Some(name);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library_with_book(copies: u32) -> (Library, u32) {
+        let mut library = Library::new();
+        let id = library
+            .add_book("Dune".to_string(), "Frank Herbert".to_string(), copies, None, None)
+            .unwrap();
+        library.add_user("Alice".to_string()).unwrap();
+        (library, id)
+    }
+
+    // Adds the demo book "Sample" and the demo user "Demo Dan", returning the book's ID
+    fn add_demo_records(library: &mut Library, copies: u32) -> u32 {
+        library.demo_mode = true;
+        let id = library
+            .add_book("Sample".to_string(), "Nobody".to_string(), copies, None, None)
+            .unwrap();
+        library.add_user("Demo Dan".to_string()).unwrap();
+        library.demo_mode = false;
+        id
+    }
+
+    #[test]
+    fn issue_and_return_round_trip() {
+        let (mut library, id) = library_with_book(1);
+
+        let due_date = library.issue_book_by_id(id, "alice").unwrap();
+        assert_eq!(due_date, Date::today().add_days(LOAN_PERIOD_DAYS));
+        assert_eq!(library.book(id).unwrap().available_copies, 0);
+        assert!(library.user("Alice").unwrap().holds(id));
+
+        assert_eq!(library.return_book_by_id(id, "Alice"), Ok(None));
+        assert_eq!(library.book(id).unwrap().available_copies, 1);
+        assert!(!library.user("Alice").unwrap().holds(id));
+    }
+
+    #[test]
+    fn issue_fails_when_every_copy_is_out() {
+        let (mut library, id) = library_with_book(1);
+        library.add_user("Bob".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+
+        assert_eq!(
+            library.issue_book_by_id(id, "Bob"),
+            Err(LibraryError::BookUnavailable {
+                id,
+                title: "Dune".to_string()
+            })
+        );
+        assert!(!library.user("Bob").unwrap().holds(id));
+    }
+
+    #[test]
+    fn issue_fails_when_user_already_holds_a_copy() {
+        let (mut library, id) = library_with_book(2);
+        library.issue_book_by_id(id, "Alice").unwrap();
+
+        assert_eq!(
+            library.issue_book_by_id(id, "Alice"),
+            Err(LibraryError::AlreadyHolds {
+                user: "Alice".to_string(),
+                title: "Dune".to_string()
+            })
+        );
+        assert_eq!(library.book(id).unwrap().available_copies, 1);
+    }
+
+    #[test]
+    fn issue_and_return_fail_for_unknown_user() {
+        let (mut library, id) = library_with_book(1);
+
+        assert_eq!(
+            library.issue_book_by_id(id, "Carol"),
+            Err(LibraryError::UserNotFound("Carol".to_string()))
+        );
+        assert_eq!(
+            library.return_book_by_id(id, "Carol"),
+            Err(LibraryError::UserNotFound("Carol".to_string()))
+        );
+        assert_eq!(library.book(id).unwrap().available_copies, 1);
+    }
+
+    #[test]
+    fn add_user_rejects_names_differing_only_in_case_or_spacing() {
+        let mut library = Library::new();
+        library.add_user("Bob".to_string()).unwrap();
+
+        assert_eq!(
+            library.add_user("bob".to_string()),
+            Err(LibraryError::UserExists("bob".to_string()))
+        );
+        assert_eq!(
+            library.add_user("Bob ".to_string()),
+            Err(LibraryError::UserExists("Bob".to_string()))
+        );
+        assert_eq!(library.users.len(), 1);
+        assert_eq!(library.user("BOB").unwrap().name, "Bob");
+    }
+
+    #[test]
+    fn return_fails_for_book_not_borrowed() {
+        let (mut library, id) = library_with_book(1);
+
+        assert_eq!(
+            library.return_book_by_id(id, "Alice"),
+            Err(LibraryError::NotBorrowed {
+                id,
+                title: "Dune".to_string(),
+                user: "Alice".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn return_passes_copy_to_first_reservation() {
+        let (mut library, id) = library_with_book(1);
+        library.add_user("Bob".to_string()).unwrap();
+        library.add_user("Carol".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        assert_eq!(library.reserve_book(id, "Bob"), Ok(1));
+        assert_eq!(library.reserve_book(id, "Carol"), Ok(2));
+
+        assert_eq!(library.return_book_by_id(id, "Alice"), Ok(Some("Bob".to_string())));
+        assert!(library.user("Bob").unwrap().holds(id));
+        assert_eq!(library.book(id).unwrap().available_copies, 0);
+        assert_eq!(library.book(id).unwrap().reservations, vec![library.user("Carol").unwrap().id]);
+    }
+
+    #[test]
+    fn return_skips_reservations_over_the_borrowing_limit() {
+        let (mut library, id) = library_with_book(1);
+        let other = library
+            .add_book("Emma".to_string(), "Jane Austen".to_string(), 1, None, None)
+            .unwrap();
+        library.add_user("Bob".to_string()).unwrap();
+        library.add_user("Carol".to_string()).unwrap();
+        library.set_max_borrowed(1);
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.reserve_book(id, "Bob").unwrap();
+        library.reserve_book(id, "Carol").unwrap();
+        library.issue_book_by_id(other, "Bob").unwrap();
+
+        assert_eq!(library.return_book_by_id(id, "Alice"), Ok(Some("Carol".to_string())));
+        assert!(!library.user("Bob").unwrap().holds(id));
+        let bob = library.user("Bob").unwrap().id;
+        assert_eq!(library.book(id).unwrap().reservations, vec![bob]);
+
+        // Bob kept his place and gets the next copy once he is under the limit again
+        library.return_book_by_id(other, "Bob").unwrap();
+        assert_eq!(library.return_book_by_id(id, "Carol"), Ok(Some("Bob".to_string())));
+        assert!(library.book(id).unwrap().reservations.is_empty());
+    }
+
+    #[test]
+    fn reserve_fails_for_book_already_held() {
+        let (mut library, id) = library_with_book(1);
+        library.issue_book_by_id(id, "Alice").unwrap();
+
+        assert_eq!(
+            library.reserve_book(id, "Alice"),
+            Err(LibraryError::AlreadyHolds {
+                user: "Alice".to_string(),
+                title: "Dune".to_string()
+            })
+        );
+        assert!(library.book(id).unwrap().reservations.is_empty());
+    }
+
+    #[test]
+    fn ghost_holds_finds_entries_for_books_with_no_copy_out() {
+        let (mut library, id) = library_with_book(1);
+        library.issue_book_by_id(id, "Alice").unwrap();
+        // A hand edit puts the copy back on the shelf but leaves Alice's entry behind
+        library.books[0].available_copies = 1;
+
+        assert_eq!(library.ghost_holds(), vec![("Alice".to_string(), id)]);
+        assert!(library.ambiguous_holds().is_empty());
+
+        assert_eq!(library.repair_ghost_holds(), 1);
+        assert!(!library.user("Alice").unwrap().holds(id));
+        assert!(library.ghost_holds().is_empty());
+        assert_eq!(library.book(id).unwrap().available_copies, 1);
+    }
+
+    #[test]
+    fn extra_holders_are_ambiguous_and_never_auto_repaired() {
+        let (mut library, id) = library_with_book(2);
+        library.add_user("Bob".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.issue_book_by_id(id, "Bob").unwrap();
+        library.books[0].available_copies = 1;
+
+        assert!(library.ghost_holds().is_empty());
+        assert_eq!(
+            library.ambiguous_holds(),
+            vec![(id, vec!["Alice".to_string(), "Bob".to_string()])]
+        );
+        assert_eq!(library.repair_ghost_holds(), 0);
+        assert!(library.user("Alice").unwrap().holds(id));
+        assert!(library.user("Bob").unwrap().holds(id));
+
+        assert_eq!(library.remove_stale_hold("bob", id), Ok(()));
+        assert!(!library.user("Bob").unwrap().holds(id));
+        assert!(library.ambiguous_holds().is_empty());
+        assert_eq!(
+            library.remove_stale_hold("Alice", id),
+            Err(LibraryError::HoldsConsistent(id))
+        );
+    }
+
+    #[test]
+    fn suspended_user_cannot_borrow_or_reserve_but_can_return() {
+        let (mut library, id) = library_with_book(1);
+        library.add_user("Bob".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.suspend_user("alice").unwrap();
+        library.suspend_user("Bob").unwrap();
+
+        assert_eq!(
+            library.reserve_book(id, "Bob"),
+            Err(LibraryError::UserSuspended("Bob".to_string()))
+        );
+        assert_eq!(library.return_book_by_id(id, "Alice"), Ok(None));
+        assert_eq!(
+            library.issue_book_by_id(id, "Alice"),
+            Err(LibraryError::UserSuspended("Alice".to_string()))
+        );
+        assert_eq!(
+            library.suspend_user("Alice"),
+            Err(LibraryError::AlreadySuspended("Alice".to_string()))
+        );
+
+        library.reinstate_user("Alice").unwrap();
+        assert!(library.issue_book_by_id(id, "Alice").is_ok());
+    }
+
+    #[test]
+    fn similar_titles_respects_the_threshold() {
+        let mut library = Library::new();
+        let id = library
+            .add_book("Harry Potter 1".to_string(), "J.K. Rowling".to_string(), 1, None, None)
+            .unwrap();
+        library
+            .add_book("The Crow".to_string(), "James O'Barr".to_string(), 1, None, None)
+            .unwrap();
+
+        // Three of the four distinct words are shared
+        let similar: Vec<u32> = library
+            .similar_titles("Harry Potter Book 1", library.similarity_threshold)
+            .iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(similar, vec![id]);
+        assert!(library.similar_titles("Harry Potter Book 1", 0.8).is_empty());
+        assert!(library.similar_titles("Dune", library.similarity_threshold).is_empty());
+    }
+
+    #[test]
+    fn query_books_ands_every_given_criterion() {
+        let mut library = Library::new();
+        let murder = library
+            .add_book(
+                "Murder on the Orient Express".to_string(),
+                "Agatha Christie".to_string(),
+                1,
+                None,
+                None,
+            )
+            .unwrap();
+        let poirot = library
+            .add_book("Poirot Investigates".to_string(), "Agatha Christie".to_string(), 1, None, None)
+            .unwrap();
+        let other = library
+            .add_book("Murder Must Advertise".to_string(), "Dorothy L. Sayers".to_string(), 1, None, None)
+            .unwrap();
+        library.add_user("Alice".to_string()).unwrap();
+        library.issue_book_by_id(poirot, "Alice").unwrap();
+
+        let ids = |filter: BookFilter| -> Vec<u32> { library.query_books(&filter).iter().map(|b| b.id).collect() };
+        assert_eq!(ids(BookFilter::default()), vec![murder, poirot, other]);
+        assert_eq!(
+            ids(BookFilter {
+                author_contains: Some("christie".to_string()),
+                ..BookFilter::default()
+            }),
+            vec![murder, poirot]
+        );
+        assert_eq!(
+            ids(BookFilter {
+                author_contains: Some("christie".to_string()),
+                available_only: true,
+                ..BookFilter::default()
+            }),
+            vec![murder]
+        );
+        assert_eq!(
+            ids(BookFilter {
+                title_contains: Some("MURDER".to_string()),
+                author_contains: Some("sayers".to_string()),
+                available_only: false,
+            }),
+            vec![other]
+        );
+        assert!(ids(BookFilter {
+            title_contains: Some("poirot".to_string()),
+            available_only: true,
+            ..BookFilter::default()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn books_for_age_lists_only_matching_ratings() {
+        let mut library = Library::new();
+        let gruffalo = library
+            .add_book(
+                "The Gruffalo".to_string(),
+                "Julia Donaldson".to_string(),
+                1,
+                Some("Children".to_string()),
+                None,
+            )
+            .unwrap();
+        library
+            .add_book("Dune".to_string(), "Frank Herbert".to_string(), 1, Some("Adult".to_string()), None)
+            .unwrap();
+        library
+            .add_book("Atlas".to_string(), "Unknown".to_string(), 1, None, None)
+            .unwrap();
+
+        let children: Vec<u32> = library.books_for_age("children").iter().map(|b| b.id).collect();
+        assert_eq!(children, vec![gruffalo]);
+        assert!(library.books_for_age("YA").is_empty());
+    }
+
+    fn index_ids(library: &Library, query: &str) -> Vec<u32> {
+        library.index_search(query).iter().map(|b| b.id).collect()
+    }
+
+    #[test]
+    fn search_index_follows_add_edit_and_remove() {
+        let (mut library, id) = library_with_book(1);
+        assert_eq!(index_ids(&library, "du herb"), vec![id]);
+
+        library
+            .edit_book(id, Some("Children of Dune".to_string()), None)
+            .unwrap();
+        assert_eq!(index_ids(&library, "child"), vec![id]);
+        assert_eq!(index_ids(&library, "dune"), vec![id]);
+
+        library.edit_book(id, Some("Emma".to_string()), None).unwrap();
+        assert!(index_ids(&library, "dune").is_empty());
+        assert_eq!(index_ids(&library, "emm"), vec![id]);
+
+        library.remove_book(id).unwrap();
+        assert!(index_ids(&library, "emm").is_empty());
+        assert!(library.search_index.is_empty());
+    }
+
+    #[test]
+    fn search_index_is_rebuilt_on_load() {
+        let (library, id) = library_with_book(1);
+        let path = std::env::temp_dir().join(format!("library_index_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        library.save_to_file(path).unwrap();
+
+        let loaded = Library::load_from_file(path);
+        let _ = fs::remove_file(path);
+        assert_eq!(index_ids(&loaded.unwrap(), "dune"), vec![id]);
+    }
+
+    // Reference answer: every query word is a prefix of a title or author word
+    fn scan_ids(library: &Library, query: &str) -> Vec<u32> {
+        let prefixes = title_tokens(query);
+        if prefixes.is_empty() {
+            return Vec::new();
+        }
+        library
+            .books
+            .iter()
+            .filter(|b| {
+                let tokens = book_tokens(b);
+                prefixes.iter().all(|p| tokens.iter().any(|t| t.starts_with(p.as_str())))
+            })
+            .map(|b| b.id)
+            .collect()
+    }
+
+    fn large_library(books: u32) -> Library {
+        let mut library = Library::new();
+        let words = ["river", "night", "garden", "stone", "winter", "crow", "harbour", "silver"];
+        let authors = ["Rowling", "Christie", "Herbert", "Austen", "Tolkien"];
+        for i in 0..books {
+            let title = format!(
+                "The {} {} {}",
+                words[i as usize % words.len()],
+                words[(i as usize / 7) % words.len()],
+                i
+            );
+            let author = authors[(i as usize / 3) % authors.len()].to_string();
+            library.add_book(title, author, 1, None, None).unwrap();
+        }
+        library
+    }
+
+    #[test]
+    fn index_search_matches_a_linear_scan() {
+        let library = large_library(300);
+        for query in ["riv", "crow ro", "the", "SIL chris", "winter garden 1", "nothing", "", "  "] {
+            assert_eq!(index_ids(&library, query), scan_ids(&library, query), "query {:?}", query);
+        }
+    }
+
+    // Run with `cargo test -- --ignored --nocapture` to compare the index with substring search
+    #[test]
+    #[ignore]
+    fn bench_index_search_against_substring_search() {
+        let library = large_library(5000);
+        let queries = ["riv", "crow", "harbour sil", "tolk", "night 42"];
+        let rounds = 200;
+
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            for query in queries {
+                std::hint::black_box(library.index_search(query));
+            }
+        }
+        let indexed = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..rounds {
+            for query in queries {
+                std::hint::black_box(library.search_books(query).unwrap());
+            }
+        }
+        let scanned = start.elapsed();
+
+        println!(
+            "5000 books, {} queries: index {:?}, substring scan {:?}",
+            rounds * queries.len(),
+            indexed,
+            scanned
+        );
+    }
+
+    #[test]
+    fn full_banner_counts_overdue_loans() {
+        let (mut library, id) = library_with_book(3);
+        library.add_user("Bob".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.issue_book_by_id(id, "Bob").unwrap();
+        library.users[0].borrowed_books[0].due_date = Some(Date::today().add_days(-1));
+        library.startup_banner = BannerMode::Full;
+
+        assert_eq!(
+            library.banner(),
+            "Library initialized\n  Books: 1 (3 copies: 2 on loan, 1 overdue, 1 available)\n  Users: 2 (0 suspended)"
+        );
+    }
+
+    #[test]
+    fn stats_leave_out_demo_records_when_excluded() {
+        let (mut library, id) = library_with_book(2);
+        let demo_book = add_demo_records(&mut library, 4);
+        library.issue_book_by_id(demo_book, "Demo Dan").unwrap();
+        library.issue_book_by_id(demo_book, "Alice").unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+
+        assert_eq!(library.stats().books, 2);
+        assert_eq!(library.stats().users, 2);
+
+        library.exclude_demo = true;
+        assert_eq!(
+            library.stats(),
+            LibraryStats {
+                books: 1,
+                books_issued: 1,
+                books_available: 1,
+                total_copies: 2,
+                copies_on_loan: 1,
+                users: 1,
+                most_borrowed: Some(("Alice".to_string(), 2)),
+            }
+        );
+    }
+
+    #[test]
+    fn purge_demo_returns_copies_demo_users_held() {
+        let (mut library, id) = library_with_book(2);
+        let demo_book = add_demo_records(&mut library, 1);
+        library.add_user("Bob".to_string()).unwrap();
+        library.issue_book_by_id(id, "Demo Dan").unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.issue_book_by_id(demo_book, "Alice").unwrap();
+        library.reserve_book(id, "Bob").unwrap();
+
+        assert_eq!(library.purge_demo(), (1, 1));
+        let book = library.book(id).unwrap();
+        assert_eq!(book.available_copies, 1);
+        assert_eq!(book.copies_out(), 1);
+        assert!(book.reservations.contains(&library.user("Bob").unwrap().id));
+        assert_eq!(library.user("Alice").unwrap().borrowed_books.len(), 1);
+        assert!(library.ghost_holds().is_empty());
+        assert!(library.ambiguous_holds().is_empty());
+    }
+
+    #[test]
+    fn load_clamps_available_copies_to_total() {
+        let path = std::env::temp_dir().join(format!("library_clamp_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let data = r#"{"books": [{"id": 1, "title": "Dune", "author": "Frank Herbert",
+            "total_copies": 2, "available_copies": 5}], "users": []}"#;
+        fs::write(path, data).unwrap();
+
+        let loaded = Library::load_from_file(path);
+        let _ = fs::remove_file(path);
+        let library = loaded.unwrap();
+        assert_eq!(library.book(1).unwrap().available_copies, 2);
+        assert_eq!(library.book(1).unwrap().copies_out(), 0);
+    }
+
+    #[test]
+    fn overdue_report_hides_demo_loans_when_excluded() {
+        let (mut library, id) = library_with_book(2);
+        let demo_book = add_demo_records(&mut library, 1);
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.issue_book_by_id(demo_book, "Alice").unwrap();
+        library.issue_book_by_id(id, "Demo Dan").unwrap();
+        let later = Date::today().add_days(LOAN_PERIOD_DAYS + 1);
+
+        assert_eq!(library.overdue_books(later).len(), 3);
+        library.exclude_demo = true;
+        let overdue: Vec<(String, u32)> = library
+            .overdue_books(later)
+            .into_iter()
+            .map(|(user, book_id, _)| (user, book_id))
+            .collect();
+        assert_eq!(overdue, vec![("Alice".to_string(), id)]);
+    }
+
+    #[test]
+    fn search_books_matches_substrings_and_rejects_blank_queries() {
+        let mut library = Library::new();
+        let rowling = library
+            .add_book("Harry Potter".to_string(), "J.K. Rowling".to_string(), 1, None, None)
+            .unwrap();
+        let crow = library
+            .add_book("The Crow".to_string(), "James O'Barr".to_string(), 1, None, None)
+            .unwrap();
+
+        let ids: Vec<u32> = library.search_books("roW").unwrap().iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![rowling, crow]);
+        assert!(library.search_books("zzz").unwrap().is_empty());
+        assert!(matches!(library.search_books(""), Err(LibraryError::EmptyQuery)));
+        assert!(matches!(library.search_books("   "), Err(LibraryError::EmptyQuery)));
+    }
+
+    // Files from before copy counts kept one record per physical copy, so a title can
+    // still appear under two IDs
+    fn library_with_two_records_of_a_title() -> Library {
+        let data = r#"{
+            "books": [
+                {"id": 1, "title": "Dune", "author": "Frank Herbert", "is_issued": false},
+                {"id": 2, "title": "Dune", "author": "Frank Herbert", "is_issued": false},
+                {"id": 3, "title": "Emma", "author": "Jane Austen", "is_issued": false}
+            ],
+            "users": [
+                {"id": 1, "name": "Alice", "borrowed_books": []},
+                {"id": 2, "name": "Bob", "borrowed_books": []}
+            ]
+        }"#;
+        let path = std::env::temp_dir().join(format!("library_swap_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, data).unwrap();
+        let library = Library::load_from_file(path);
+        let _ = fs::remove_file(path);
+        library.unwrap()
+    }
+
+    #[test]
+    fn swap_copy_moves_the_loan_to_another_copy_of_the_title() {
+        let mut library = library_with_two_records_of_a_title();
+        library.issue_book_by_id(1, "Alice").unwrap();
+
+        assert_eq!(library.swap_copy("Alice", 1, 2), Ok(()));
+        assert_eq!(library.book(1).unwrap().available_copies, 1);
+        assert_eq!(library.book(2).unwrap().available_copies, 0);
+        assert!(library.user("Alice").unwrap().holds(2));
+        assert!(!library.user("Alice").unwrap().holds(1));
+
+        assert_eq!(library.swap_copy("Alice", 2, 3), Err(LibraryError::NotSameTitle(2, 3)));
+        assert!(matches!(
+            library.swap_copy("Alice", 1, 2),
+            Err(LibraryError::NotBorrowed { id: 1, .. })
+        ));
+        library.issue_book_by_id(1, "Bob").unwrap();
+        assert!(matches!(
+            library.swap_copy("Alice", 2, 1),
+            Err(LibraryError::BookUnavailable { id: 1, .. })
+        ));
+        assert!(library.user("Alice").unwrap().holds(2));
+    }
+
+    #[test]
+    fn edit_book_trims_and_rejects_blanks_and_duplicates() {
+        let (mut library, id) = library_with_book(1);
+        let emma = library
+            .add_book("Emma".to_string(), "Jane Austen".to_string(), 1, None, None)
+            .unwrap();
+
+        library
+            .edit_book(id, Some("  Dune Messiah ".to_string()), None)
+            .unwrap();
+        assert_eq!(library.book(id).unwrap().title, "Dune Messiah");
+
+        assert!(matches!(
+            library.edit_book(id, Some("   ".to_string()), None),
+            Err(LibraryError::BlankField("Title"))
+        ));
+        assert!(matches!(
+            library.edit_book(id, None, Some(String::new())),
+            Err(LibraryError::BlankField("Author"))
+        ));
+        assert!(matches!(
+            library.edit_book(id, Some("emma".to_string()), Some("JANE AUSTEN".to_string())),
+            Err(LibraryError::DuplicateBook(existing)) if existing == emma
+        ));
+        assert_eq!(library.book(id).unwrap().author, "Frank Herbert");
+
+        // A book's own title and author never count as a duplicate
+        library
+            .edit_book(emma, Some("EMMA".to_string()), None)
+            .unwrap();
+        assert_eq!(library.book(emma).unwrap().title, "EMMA");
+    }
+
+    #[test]
+    fn add_copies_serves_the_reservation_queue() {
+        let (mut library, id) = library_with_book(1);
+        library.add_user("Bob".to_string()).unwrap();
+        library.add_user("Carol".to_string()).unwrap();
+        library.add_user("Dave".to_string()).unwrap();
+        library.issue_book_by_id(id, "Alice").unwrap();
+        library.reserve_book(id, "Bob").unwrap();
+        library.reserve_book(id, "Carol").unwrap();
+        library.reserve_book(id, "Dave").unwrap();
+        library.suspend_user("Bob").unwrap();
+
+        assert_eq!(library.add_copies(id, 1), Ok(vec!["Carol".to_string()]));
+        let bob = library.user("Bob").unwrap().id;
+        let dave = library.user("Dave").unwrap().id;
+        assert_eq!(library.book(id).unwrap().reservations, vec![bob, dave]);
+
+        library.reinstate_user("Bob").unwrap();
+        // An ID left behind by a user who no longer exists is dropped, not served
+        library.books[0].reservations.insert(0, 99);
+        assert_eq!(
+            library.add_copies(id, 3),
+            Ok(vec!["Bob".to_string(), "Dave".to_string()])
+        );
+        let book = library.book(id).unwrap();
+        assert!(book.reservations.is_empty());
+        assert_eq!((book.total_copies, book.available_copies), (5, 1));
+    }
+
+    #[test]
+    fn add_copies_refuses_to_overflow_the_counts() {
+        let (mut library, id) = library_with_book(1);
+        library.books[0].total_copies = u32::MAX;
+        library.books[0].available_copies = u32::MAX;
+
+        assert_eq!(library.add_copies(id, 1), Err(LibraryError::TooManyCopies(id)));
+        let book = library.book(id).unwrap();
+        assert_eq!((book.total_copies, book.available_copies), (u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn return_all_for_user_shelves_copies_and_reports_dangling_loans() {
+        let data = r#"{
+            "books": [
+                {"id": 1, "title": "Dune", "author": "Frank Herbert", "is_issued": true}
+            ],
+            "users": [
+                {"id": 1, "name": "Alice", "borrowed_books": [1, 9]}
+            ]
+        }"#;
+        let path = std::env::temp_dir().join(format!("library_return_all_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, data).unwrap();
+        let library = Library::load_from_file(path);
+        let _ = fs::remove_file(path);
+        let mut library = library.unwrap();
+        let emma = library
+            .add_book("Emma".to_string(), "Jane Austen".to_string(), 2, None, None)
+            .unwrap();
+        library.issue_book_by_id(emma, "Alice").unwrap();
+
+        let result = library.return_all_for_user("alice").unwrap();
+        assert_eq!(
+            result.returned,
+            vec![("Dune".to_string(), None), ("Emma".to_string(), None)]
+        );
+        assert_eq!(result.unknown, vec![9]);
+        assert_eq!(library.book(1).unwrap().available_copies, 1);
+        assert_eq!(library.book(emma).unwrap().available_copies, 2);
+        assert!(library.user("Alice").unwrap().borrowed_books.is_empty());
+
+        let result = library.return_all_for_user("Alice").unwrap();
+        assert!(result.returned.is_empty() && result.unknown.is_empty());
+    }
+
+    #[test]
+    fn removed_ids_are_never_reused() {
+        let (mut library, id) = library_with_book(1);
+        library.remove_book(id).unwrap();
+        let next = library
+            .add_book("Emma".to_string(), "Jane Austen".to_string(), 1, None, None)
+            .unwrap();
+        assert_ne!(next, id);
+
+        // Files written before the counters were stored pick up after their highest IDs
+        let data = r#"{
+            "books": [
+                {"id": 4, "title": "Dune", "author": "Frank Herbert", "is_issued": false}
+            ],
+            "users": [
+                {"id": 7, "name": "Alice", "borrowed_books": []}
+            ]
+        }"#;
+        let path = std::env::temp_dir().join(format!("library_next_ids_test_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, data).unwrap();
+        let library = Library::load_from_file(path);
+        let _ = fs::remove_file(path);
+        let mut library = library.unwrap();
+        let book = library
+            .add_book("Emma".to_string(), "Jane Austen".to_string(), 1, None, None)
+            .unwrap();
+        assert_eq!(book, 5);
+        library.add_user("Bob".to_string()).unwrap();
+        assert_eq!(library.user("Bob").unwrap().id, 8);
+    }
+}
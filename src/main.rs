@@ -1,187 +1,680 @@
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::io;
-
-#[derive(Serialize, Deserialize, PartialEq)]
-struct Book {
-    id: u32,
-    title: String,
-    author: String,
-    is_issued: bool,
+use library_management::{Book, BookFilter, BookSort, Date, Library, LibraryError, ReturnedBooks, User};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+const DEFAULT_DATA_FILE: &str = "library.json";
+const MAX_PROMPT_ATTEMPTS: u32 = 3;
+
+// Asks for a required value, re-prompting on blank input; None cancels the operation
+fn prompt_required<R: BufRead>(input: &mut R, prompt: &str, field: &str) -> Option<String> {
+    for _ in 0..MAX_PROMPT_ATTEMPTS {
+        println!("{}", prompt);
+        let mut line = String::new();
+        let bytes = input.read_line(&mut line).expect("Failed to read input");
+        if bytes == 0 {
+            break;
+        }
+
+        let value = line.trim();
+        if !value.is_empty() {
+            return Some(value.to_string());
+        }
+        println!("Error: {} cannot be empty!", field);
+    }
+
+    println!("Operation cancelled.");
+    None
 }
 
-#[derive(Serialize, Deserialize)]
-struct User {
-    id: u32,
-    name: String,
-    borrowed_books: Vec<u32>,
+fn prompt_book_id<R: BufRead>(input: &mut R, prompt: &str) -> Option<u32> {
+    let id = prompt_required(input, prompt, "Book ID")?;
+    match id.parse() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            println!("Invalid input! Book ID must be a number.");
+            None
+        }
+    }
+}
+
+fn print_book(book: &Book) {
+    let status = format!("{}/{} available", book.available_copies, book.total_copies);
+    let rating = match &book.age_rating {
+        Some(rating) => format!(", Age Rating: {}", rating),
+        None => String::new(),
+    };
+    println!(
+        "ID: {}, Title: {}, Author: {}, Status: {}{}",
+        book.id, book.title, book.author, status, rating
+    );
 }
 
-#[derive(Serialize, Deserialize)]
-struct Library {
-    books: Vec<Book>,
-    users: Vec<User>,
+fn print_book_details(book: &Book) {
+    println!("ID: {}", book.id);
+    println!("Title: {}", book.title);
+    println!("Author: {}", book.author);
+    println!("Status: {}/{} available", book.available_copies, book.total_copies);
+    println!("Age Rating: {}", book.age_rating.as_deref().unwrap_or("(none)"));
+    println!("Added By: {}", book.added_by.as_deref().unwrap_or("(not recorded)"));
+    println!("Reservations: {}", book.reservations.len());
 }
 
-impl Library {
-    fn new() -> Self {
-        Library {
-            books: Vec::new(),
-            users: Vec::new(),
+fn display_books(library: &Library, sort: BookSort) {
+    let books = library.sorted_books(sort);
+    if books.is_empty() {
+        println!("No books available.");
+    } else {
+        println!("\nLibrary Books:");
+        for book in books {
+            print_book(book);
         }
     }
+}
 
-    fn load_from_file(filename: &str) -> Result<Self, String> {
-        if std::path::Path::new(filename).exists() {
-            let data = fs::read_to_string(filename).map_err(|e| format!("Failed to read file: {}", e))?;
-            let library: Library = serde_json::from_str(&data).map_err(|e| format!("Failed to parse JSON: {}", e))?;
-            Ok(library)
-        } else {
-            Ok(Library::new())
+fn display_available_books(library: &Library) {
+    let available: Vec<&Book> = library.visible_books().filter(|b| b.is_available()).collect();
+    if available.is_empty() {
+        println!("No books available.");
+    } else {
+        println!("\nAvailable Books:");
+        for book in available {
+            print_book(book);
         }
     }
+}
+
+fn display_users(library: &Library) {
+    let users: Vec<&User> = library.visible_users().collect();
+    if users.is_empty() {
+        println!("No users registered.");
+        return;
+    }
 
-    fn save_to_file(&self, filename: &str) -> Result<(), String> {
-        let data = serde_json::to_string(self).map_err(|e| format!("Failed to serialize to JSON: {}", e))?;
-        fs::write(filename, data).map_err(|e| format!("Failed to write file: {}", e))?;
-        Ok(())
-    }
-
-    fn add_book(&mut self, title: String, author: String) {
-        let id = (self.books.len() as u32) + 1;
-        println!("Book '{}' by '{}' added", title, author);
-        self.books.push(Book {
-            id,
-            title,
-            author,
-            is_issued: false,
-        });
-    }
-
-    fn add_user(&mut self, name: String) {
-        if !self.users.iter().any(|u| u.name == name) {
-            let id = (self.users.len() as u32) + 1;
-            println!("User '{}' added", name);
-            self.users.push(User {
-                id,
-                name,
-                borrowed_books: Vec::new(),
-            });
-        } else {
-            println!("Error: User '{}' already exists!", name);
+    println!("\nLibrary Users:");
+    for user in users {
+        let suspended = if user.suspended { " (suspended)" } else { "" };
+        println!("ID: {}, Name: {}{}", user.id, user.name, suspended);
+        if user.borrowed_books.is_empty() {
+            println!("  Borrowed: (none)");
         }
+        for record in &user.borrowed_books {
+            // Report IDs that no longer resolve instead of hiding the inconsistency
+            let title = match library.book(record.book_id) {
+                Some(book) => book.title.clone(),
+                None => format!("(unknown book #{})", record.book_id),
+            };
+            match record.due_date {
+                Some(due_date) => println!("  Borrowed: {} (due {})", title, due_date),
+                None => println!("  Borrowed: {}", title),
+            }
+        }
+    }
+}
+
+fn display_stats(library: &Library) {
+    let stats = library.stats();
+    println!("\nLibrary Statistics:");
+    println!("Total books: {}", stats.books);
+    println!("Books with copies issued: {}", stats.books_issued);
+    println!("Books with copies available: {}", stats.books_available);
+    println!("Total copies: {}", stats.total_copies);
+    println!("Copies currently on loan: {}", stats.copies_on_loan);
+    println!("Total users: {}", stats.users);
+    match stats.most_borrowed {
+        Some((name, loans)) => println!("Most borrowed: {} ({} books)", name, loans),
+        None => println!("Most borrowed: (none)"),
+    }
+}
+
+// The menu, the repl and one-shot commands share these: make the change and say how it went
+fn report_added_book(library: &Library, id: u32) {
+    if let Some(book) = library.book(id) {
+        println!(
+            "Book '{}' by '{}' added ({} copies)",
+            book.title, book.author, book.total_copies
+        );
     }
+}
 
-    fn display_books(&self) {
-        if self.books.is_empty() {
-            println!("No books available.");
-        } else {
-            println!("\nLibrary Books:");
-            for book in &self.books {
-                let status = if book.is_issued { "Issued" } else { "Available" };
+fn add_copies(library: &mut Library, id: u32, copies: u32) -> bool {
+    match library.add_copies(id, copies) {
+        Ok(served) => {
+            if let Some(book) = library.book(id) {
                 println!(
-                    "ID: {}, Title: {}, Author: {}, Status: {}",
-                    book.id, book.title, book.author, status
+                    "Added {} copies of '{}' (now {}/{} available)",
+                    copies, book.title, book.available_copies, book.total_copies
                 );
             }
+            for user in served {
+                report_passed_on(library, id, &user);
+            }
+            true
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            false
         }
     }
+}
 
-    fn issue_book(&mut self, title: String, user: &str) {
-        // Check if user exists
-        if !self.users.iter().any(|u| u.name == user) {
-            println!("No user found with name '{}'. Please register first!", user);
-            return;
+// The add_user, issue_book and return_book helpers report success themselves and
+// leave the error to the caller, which prints it to stdout or stderr depending on the mode
+fn add_user(library: &mut Library, name: &str) -> Result<(), LibraryError> {
+    library.add_user(name.to_string())?;
+    println!("User '{}' added", name.trim());
+    Ok(())
+}
+
+fn issue_book(library: &mut Library, id: u32, user: &str) -> Result<(), LibraryError> {
+    let due_date = library.issue_book_by_id(id, user)?;
+    let title = library.book(id).map_or("", |b| b.title.as_str());
+    let user = library.user(user).map_or(user, |u| u.name.as_str());
+    println!("Book '{}' issued to user '{}', due {}", title, user, due_date);
+    Ok(())
+}
+
+// Announces that a returned copy went straight to the user who had reserved it
+fn report_passed_on(library: &Library, id: u32, user: &str) {
+    let title = library.book(id).map_or("", |b| b.title.as_str());
+    let due_date = library
+        .user(user)
+        .and_then(|u| u.borrowed_books.iter().find(|r| r.book_id == id))
+        .and_then(|r| r.due_date);
+    match due_date {
+        Some(due_date) => println!("Reserved copy of '{}' issued to user '{}', due {}", title, user, due_date),
+        None => println!("Reserved copy of '{}' issued to user '{}'", title, user),
+    }
+}
+
+fn return_book(library: &mut Library, id: u32, user: &str) -> Result<(), LibraryError> {
+    let passed_to = library.return_book_by_id(id, user)?;
+    let title = library.book(id).map_or("", |b| b.title.as_str());
+    let user = library.user(user).map_or(user, |u| u.name.as_str());
+    println!("Book '{}' returned by user '{}'", title, user);
+    if let Some(next) = passed_to {
+        report_passed_on(library, id, &next);
+    }
+    Ok(())
+}
+
+// Removes a boolean `flag` from the argument list, returning whether it was present
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|a| a != flag);
+    args.len() != before
+}
+
+// Removes `flag <value>` from the argument list, returning the value
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == flag) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
         }
+        Some(_) => Err(format!("{} needs a value", flag)),
+        None => Ok(None),
+    }
+}
 
-        // Check if book exists and is available
-        let book_exists_and_available = self.books.iter().any(|b| b.title == title && !b.is_issued);
-        if !book_exists_and_available {
-            println!("No available book found with title '{}'.", title);
-            return;
+fn display_query_results(books: &[&Book]) {
+    if books.is_empty() {
+        println!("No matching books found.");
+    } else {
+        for book in books {
+            print_book(book);
         }
+    }
+}
 
-        // Find book and mark as issued
-        let mut book_id = 0;
-        for book in self.books.iter_mut() {
-            if book.title == title && !book.is_issued {
-                book.is_issued = true;
-                book_id = book.id;
-                break;
-            }
+// Parses query flags such as `--author christie --available`
+fn parse_book_filter(args: &[&str]) -> Result<BookFilter, String> {
+    let mut filter = BookFilter::default();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--title" => match args.next() {
+                Some(value) => filter.title_contains = Some(value.to_string()),
+                None => return Err("--title needs a value".to_string()),
+            },
+            "--author" => match args.next() {
+                Some(value) => filter.author_contains = Some(value.to_string()),
+                None => return Err("--author needs a value".to_string()),
+            },
+            "--available" => filter.available_only = true,
+            other => return Err(format!("Unknown query option '{}'", other)),
         }
+    }
+    Ok(filter)
+}
 
-        // Update user's borrowed_books
-        for user_record in self.users.iter_mut() {
-            if user_record.name == user {
-                user_record.borrowed_books.push(book_id);
-                println!("Book '{}' issued to user '{}'", title, user);
-                break;
+// Splits a REPL line into words, treating "double quoted" text as a single argument
+fn parse_command_line(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_arg = true;
             }
         }
     }
 
-    fn return_book(&mut self, title: String, user: &str) {
-        // Check if user exists
-        if !self.users.iter().any(|u| u.name == user) {
-            println!("No user found with name '{}'.", user);
-            return;
+    if in_quotes {
+        return Err("Unterminated quote".to_string());
+    }
+    if has_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn print_repl_help() {
+    let commands = [
+        ("add \"<title>\" \"<author>\" [copies [age rating]]", "Add a book"),
+        ("copies <book id> <n>", "Add copies of an existing book"),
+        ("user \"<name>\"", "Add a user"),
+        ("issue <book id> <user>", "Issue a book to a user"),
+        ("return <book id> <user>", "Return a book from a user"),
+        ("list [available]", "List all (or only available) books"),
+        ("search \"<term>\"", "Find books by title or author"),
+        ("query [--title t] [--author a] [--available]", "Find books matching all given criteria"),
+        ("help", "Show this help"),
+        ("quit", "Save and exit"),
+    ];
+    println!("Commands:");
+    for (usage, description) in commands {
+        println!("  {:<47} {}", usage, description);
+    }
+}
+
+// Persists the previous operation's changes, if it made any
+fn autosave(library: &mut Library, data_file: &str) {
+    if let Err(e) = library.save_if_changed(data_file) {
+        eprintln!("Error saving data: {}", e);
+    }
+}
+
+fn run_repl(library: &mut Library, data_file: &str, operator: Option<&str>) {
+    println!("Type 'help' for a list of commands.");
+
+    loop {
+        autosave(library, data_file);
+        print!("library> ");
+        io::stdout().flush().expect("Failed to flush output");
+
+        let mut line = String::new();
+        let bytes = io::stdin()
+            .read_line(&mut line)
+            .expect("Failed to read input");
+        if bytes == 0 {
+            // End of input behaves like quit
+            autosave(library, data_file);
+            break;
         }
 
-        // Check if book exists and is issued
-        let book_id = match self.books.iter().find(|b| b.title == title && b.is_issued) {
-            Some(book) => book.id,
-            None => {
-                println!("No issued book found with title '{}'.", title);
-                return;
+        let args = match parse_command_line(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                println!("Error: {}", e);
+                continue;
             }
         };
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
 
-        // Check if user borrowed the book
-        let user_borrowed = self.users.iter().any(|u| u.name == user && u.borrowed_books.contains(&book_id));
-        if !user_borrowed {
-            println!("User '{}' did not borrow book '{}'.", user, title);
-            return;
-        }
+        match args.as_slice() {
+            [] => {}
+            ["add", title, author, extra @ ..] if extra.len() <= 2 => {
+                let copies = match extra.first().map(|c| c.parse::<u32>()) {
+                    Some(Ok(copies)) => copies,
+                    Some(Err(_)) => {
+                        println!("Error: Number of copies must be a number.");
+                        continue;
+                    }
+                    None => 1,
+                };
 
-        // Update book status
-        for book in self.books.iter_mut() {
-            if book.title == title && book.is_issued {
-                book.is_issued = false;
+                if title.is_empty() || author.is_empty() {
+                    println!("Error: Title and author cannot be empty!");
+                } else {
+                    if library.find_book(title, author).is_none() {
+                        for book in library.similar_titles(title, library.similarity_threshold) {
+                            println!("Warning: similar title exists: '{}' (ID {}).", book.title, book.id);
+                        }
+                    }
+                    let rating = extra.get(1).map(|r| r.to_string());
+                    match library.add_book(
+                        title.to_string(),
+                        author.to_string(),
+                        copies,
+                        rating,
+                        operator.map(|o| o.to_string()),
+                    ) {
+                        Ok(id) => report_added_book(library, id),
+                        Err(LibraryError::DuplicateBook(id)) => println!(
+                            "Error: '{}' by '{}' already exists (ID {}). Use 'copies {} <n>' to add copies.",
+                            title, author, id, id
+                        ),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+            }
+            ["copies", id, copies] => match (id.parse::<u32>(), copies.parse::<u32>()) {
+                (Ok(id), Ok(copies)) => {
+                    add_copies(library, id, copies);
+                }
+                _ => println!("Error: Book ID and number of copies must be numbers."),
+            },
+            ["user", name] => {
+                if name.is_empty() {
+                    println!("Error: Name cannot be empty!");
+                } else {
+                    if let Err(e) = add_user(library, name) {
+                        println!("Error: {}", e);
+                    }
+                }
+            }
+            ["issue", id, user] => match id.parse::<u32>() {
+                Ok(id) => {
+                    if let Err(e) = issue_book(library, id, user) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Err(_) => println!("Error: Book ID must be a number."),
+            },
+            ["return", id, user] => match id.parse::<u32>() {
+                Ok(id) => {
+                    if let Err(e) = return_book(library, id, user) {
+                        println!("Error: {}", e);
+                    }
+                }
+                Err(_) => println!("Error: Book ID must be a number."),
+            },
+            ["list"] => display_books(library, BookSort::Id),
+            ["list", "available"] => display_available_books(library),
+            ["search", query] => match library.search_books(query) {
+                Ok(books) => display_query_results(&books),
+                Err(e) => println!("Error: {}", e),
+            },
+            ["query", flags @ ..] => match parse_book_filter(flags) {
+                Ok(filter) => display_query_results(&library.query_books(&filter)),
+                Err(e) => println!("Error: {}", e),
+            },
+            ["help"] => print_repl_help(),
+            ["quit"] | ["exit"] => {
+                match library.save_to_file(data_file) {
+                    Ok(()) => println!("Data saved to {}", data_file),
+                    Err(e) => eprintln!("Error saving data: {}", e),
+                }
+                println!("Exiting...");
                 break;
             }
+            _ => println!("Unknown command or wrong arguments. Type 'help' for usage."),
+        }
+    }
+}
+
+fn print_usage() {
+    println!("Usage: library_management [--file <path>] [--operator <name>] [--demo] [--exclude-demo] [command]");
+    println!();
+    println!("Without a command the interactive menu starts.");
+    println!("The data file defaults to $LIBRARY_FILE, or {} if that is unset.", DEFAULT_DATA_FILE);
+    println!();
+    println!("Commands:");
+    println!("  repl                                   Start the command prompt");
+    println!("  add-book --title <t> --author <a> [--copies <n>] [--age-rating <r>]");
+    println!("  add-user --name <name>");
+    println!("  issue --id <book id> --user <name>");
+    println!("  return --id <book id> --user <name>");
+    println!("  list                                   List all books");
+    println!("  query [--title t] [--author a] [--available]");
+}
+
+// Parses `--name value` pairs, rejecting any flag not in `allowed`
+fn parse_command_flags<'a>(args: &[&'a str], allowed: &[&str]) -> Result<BTreeMap<&'a str, &'a str>, String> {
+    let mut flags = BTreeMap::new();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .filter(|name| allowed.contains(name))
+            .ok_or_else(|| format!("Unknown option '{}'", arg))?;
+        let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+        flags.insert(name, *value);
+    }
+    Ok(flags)
+}
+
+fn required_flag<'a>(flags: &BTreeMap<&str, &'a str>, name: &str) -> Result<&'a str, String> {
+    match flags.get(name) {
+        Some(value) if !value.trim().is_empty() => Ok(value.trim()),
+        _ => Err(format!("--{} is required", name)),
+    }
+}
+
+fn parse_id_flag(flags: &BTreeMap<&str, &str>) -> Result<u32, String> {
+    required_flag(flags, "id")?
+        .parse()
+        .map_err(|_| "--id must be a number".to_string())
+}
+
+// Command mode reports failures on stderr so stdout only carries results
+fn report_command_result(result: Result<(), LibraryError>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            false
         }
+    }
+}
 
-        // Remove book from user's borrowed_books
-        for user_record in self.users.iter_mut() {
-            if user_record.name == user {
-                if let Some(index) = user_record.borrowed_books.iter().position(|&id| id == book_id) {
-                    user_record.borrowed_books.remove(index);
+// Runs one non-interactive command and saves if it changed anything.
+// Ok(false) means the operation itself was refused (e.g. book unavailable).
+fn run_command(
+    library: &mut Library,
+    data_file: &str,
+    command: &str,
+    args: &[&str],
+    operator: Option<&str>,
+) -> Result<bool, String> {
+    let succeeded = match command {
+        "add-book" => {
+            let flags = parse_command_flags(args, &["title", "author", "copies", "age-rating"])?;
+            let title = required_flag(&flags, "title")?;
+            let author = required_flag(&flags, "author")?;
+            let copies = match flags.get("copies") {
+                Some(copies) => copies
+                    .parse()
+                    .map_err(|_| "--copies must be a number".to_string())?,
+                None => 1,
+            };
+            let rating = flags.get("age-rating").map(|r| r.to_string());
+            match library.add_book(
+                title.to_string(),
+                author.to_string(),
+                copies,
+                rating,
+                operator.map(|o| o.to_string()),
+            ) {
+                Ok(id) => {
+                    report_added_book(library, id);
+                    true
+                }
+                Err(LibraryError::DuplicateBook(id)) => {
+                    eprintln!("Error: '{}' by '{}' already exists (ID {}).", title, author, id);
+                    false
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    false
                 }
-                println!("Book '{}' returned by user '{}'", title, user);
-                break;
             }
         }
+        "add-user" => {
+            let flags = parse_command_flags(args, &["name"])?;
+            report_command_result(add_user(library, required_flag(&flags, "name")?))
+        }
+        "issue" => {
+            let flags = parse_command_flags(args, &["id", "user"])?;
+            report_command_result(issue_book(library, parse_id_flag(&flags)?, required_flag(&flags, "user")?))
+        }
+        "return" => {
+            let flags = parse_command_flags(args, &["id", "user"])?;
+            report_command_result(return_book(library, parse_id_flag(&flags)?, required_flag(&flags, "user")?))
+        }
+        "list" => {
+            parse_command_flags(args, &[])?;
+            display_books(library, BookSort::Id);
+            return Ok(true);
+        }
+        _ => return Err(format!("Unknown command '{}'", command)),
+    };
+
+    if succeeded {
+        if let Err(e) = library.save_if_changed(data_file) {
+            eprintln!("Error saving data: {}", e);
+            return Ok(false);
+        }
+    }
+    Ok(succeeded)
+}
+
+// Swaps the data file's extension for .log, or appends .log when it already ends in .log
+// so the log never overwrites the data file
+fn audit_log_path(data_file: &str) -> String {
+    let path = std::path::Path::new(data_file);
+    if path.extension().is_some_and(|ext| ext == "log") {
+        format!("{}.log", data_file)
+    } else {
+        path.with_extension("log").to_string_lossy().into_owned()
     }
 }
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let flag_error = |e: String| -> Option<String> {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    };
+    let mut operator = take_flag_value(&mut args, "--operator").unwrap_or_else(flag_error);
+
+    // --file wins over $LIBRARY_FILE, which wins over the default
+    let data_file = take_flag_value(&mut args, "--file")
+        .unwrap_or_else(flag_error)
+        .or_else(|| std::env::var("LIBRARY_FILE").ok().filter(|f| !f.is_empty()))
+        .unwrap_or_else(|| DEFAULT_DATA_FILE.to_string());
+
     // Initialize the library
-    let mut library = Library::load_from_file("library.json").unwrap_or_else(|e| {
-        eprintln!("Error loading library: {}. Starting with empty library.", e);
-        Library::new()
+    // Only an existing file can fail to load; starting empty would let autosave overwrite it
+    let mut library = Library::load_from_file(&data_file).unwrap_or_else(|e| {
+        eprintln!("Error loading library from '{}': {}", data_file, e);
+        eprintln!("Fix or move the file and try again.");
+        std::process::exit(1);
     });
-    println!("Library initialized with {} books and {} users", library.books.len(), library.users.len());
+    library.demo_mode = take_flag(&mut args, "--demo");
+    library.exclude_demo = take_flag(&mut args, "--exclude-demo");
+    // The audit log sits next to its data file, so separate libraries keep separate logs
+    library.log_file = Some(audit_log_path(&data_file));
+
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    // Only interactive sessions show the banner, so one-shot output stays clean for scripts
+    let interactive = matches!(args.as_slice(), [] | ["repl"]);
+    let banner = library.banner();
+    if interactive && !banner.is_empty() {
+        println!("{}", banner);
+    }
+
+    // Interactive sessions ask who is working when no --operator was given
+    if interactive && operator.is_none() {
+        println!("Enter operator name (blank to skip): ");
+        let mut name = String::new();
+        io::stdin()
+            .read_line(&mut name)
+            .expect("Failed to read operator name");
+        operator = Some(name.trim().to_string()).filter(|n| !n.is_empty());
+    }
+
+    match args.as_slice() {
+        // Optional command-driven mode: `library_management repl`
+        ["repl"] => {
+            run_repl(&mut library, &data_file, operator.as_deref());
+            return;
+        }
+        // One-shot search: `library_management query --author christie --available`
+        ["query", flags @ ..] => {
+            match parse_book_filter(flags) {
+                Ok(filter) => display_query_results(&library.query_books(&filter)),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        ["help"] | ["--help"] => {
+            print_usage();
+            return;
+        }
+        [] => {}
+        [command, rest @ ..] => match run_command(&mut library, &data_file, command, rest, operator.as_deref()) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                eprintln!("Run with 'help' for usage.");
+                std::process::exit(2);
+            }
+        },
+    }
 
     // Main menu loop
     loop {
+        autosave(&mut library, &data_file);
+
         println!("\nLibrary Management System");
         println!("1. Add Book");
         println!("2. Add User");
         println!("3. Issue Book");
         println!("4. Return Book");
         println!("5. Display Books");
-        println!("6. Exit");
+        println!("6. Check Data Consistency");
+        println!("7. Suspend User");
+        println!("8. Reinstate User");
+        println!("9. Swap Book Copy");
+        println!("10. List Books by Age Rating");
+        println!("11. Quick Search");
+        println!("12. Book Details");
+        println!("13. Acquisitions Report");
+        println!("14. Purge Demo Data");
+        println!("15. Remove Book");
+        println!("16. Overdue Report");
+        println!("17. Borrowing Limit");
+        println!("18. Search Books");
+        println!("19. Display Users");
+        println!("20. Statistics");
+        println!("21. Export Books to CSV");
+        println!("22. Edit Book");
+        println!("23. Return All Books for User");
+        println!("24. Audit Log");
+        println!("25. Reserve Book");
+        // Exit stays at 0 so new options can be appended without renumbering it
+        println!("0. Exit");
         println!("Enter choice: ");
 
         let mut choice = String::new();
@@ -199,92 +692,501 @@ fn main() {
 
         match choice {
             1 => {
-                println!("Enter book title: ");
-                let mut title = String::new();
+                let Some(title) = prompt_required(&mut io::stdin().lock(), "Enter book title: ", "Title") else {
+                    continue;
+                };
+                let Some(author) = prompt_required(&mut io::stdin().lock(), "Enter book author: ", "Author") else {
+                    continue;
+                };
+
+                println!("Enter number of copies (blank for 1): ");
+                let mut copies = String::new();
                 io::stdin()
-                    .read_line(&mut title)
-                    .expect("Failed to read title");
-                let title = title.trim().to_string();
+                    .read_line(&mut copies)
+                    .expect("Failed to read copies");
+                let copies = match copies.trim() {
+                    "" => 1,
+                    copies => match copies.parse::<u32>() {
+                        Ok(copies) if copies > 0 => copies,
+                        _ => {
+                            println!("Invalid input! Number of copies must be a positive number.");
+                            continue;
+                        }
+                    },
+                };
 
-                println!("Enter book author: ");
-                let mut author = String::new();
+                println!("Enter age rating (e.g. Children, YA, Adult; blank for none): ");
+                let mut rating = String::new();
                 io::stdin()
-                    .read_line(&mut author)
-                    .expect("Failed to read author");
-                let author = author.trim().to_string();
+                    .read_line(&mut rating)
+                    .expect("Failed to read age rating");
+                let rating = Some(rating.trim().to_string()).filter(|r| !r.is_empty());
 
-                if title.is_empty() || author.is_empty() {
-                    println!("Error: Title and author cannot be empty!");
-                } else {
-                    library.add_book(title, author);
+                // Exact duplicates get their own question below; only warn about near matches
+                let similar = library.similar_titles(&title, library.similarity_threshold);
+                if !similar.is_empty() && library.find_book(&title, &author).is_none() {
+                    for book in &similar {
+                        println!("Similar title exists: '{}' (ID {}).", book.title, book.id);
+                    }
+                    println!("Add anyway? (y/n): ");
+                    let mut answer = String::new();
+                    io::stdin()
+                        .read_line(&mut answer)
+                        .expect("Failed to read answer");
+                    if !answer.trim().eq_ignore_ascii_case("y") {
+                        println!("Book not added.");
+                        continue;
+                    }
+                }
+
+                match library.add_book(title.clone(), author.clone(), copies, rating, operator.clone()) {
+                    Ok(id) => report_added_book(&library, id),
+                    Err(LibraryError::DuplicateBook(id)) => {
+                        println!(
+                            "'{}' by '{}' already exists (ID {}). Add {} more copies to it? (y/n): ",
+                            title, author, id, copies
+                        );
+                        let mut answer = String::new();
+                        io::stdin()
+                            .read_line(&mut answer)
+                            .expect("Failed to read answer");
+                        if answer.trim().eq_ignore_ascii_case("y") {
+                            add_copies(&mut library, id, copies);
+                        } else {
+                            println!("Book not added.");
+                        }
+                    }
+                    Err(e) => println!("Error: {}", e),
                 }
             }
             2 => {
-                println!("Enter user name: ");
-                let mut name = String::new();
+                if let Some(name) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "Name") {
+                    if let Err(e) = add_user(&mut library, &name) {
+                        println!("Error: {}", e);
+                    }
+                }
+            }
+            3 => {
+                let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter ID of the book to issue: ") else {
+                    continue;
+                };
+                let Some(user) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "User name") else {
+                    continue;
+                };
+
+                if let Err(e) = issue_book(&mut library, id, &user) {
+                    println!("Error: {}", e);
+                }
+            }
+            4 => {
+                let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter ID of the book to return: ") else {
+                    continue;
+                };
+                let Some(user) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "User name") else {
+                    continue;
+                };
+
+                if let Err(e) = return_book(&mut library, id, &user) {
+                    println!("Error: {}", e);
+                }
+            }
+            5 => {
+                println!("Sort by: 1. ID  2. Title  3. Author (blank for ID): ");
+                let mut sort = String::new();
                 io::stdin()
-                    .read_line(&mut name)
-                    .expect("Failed to read name");
-                let name = name.trim().to_string();
+                    .read_line(&mut sort)
+                    .expect("Failed to read sort order");
+                let sort = match sort.trim() {
+                    "" | "1" => BookSort::Id,
+                    "2" => BookSort::Title,
+                    "3" => BookSort::Author,
+                    _ => {
+                        println!("Invalid choice! Please select 1–3.");
+                        continue;
+                    }
+                };
+                display_books(&library, sort);
+            }
+            6 => {
+                let ghosts = library.ghost_holds();
+                let ambiguous = library.ambiguous_holds();
+                if ghosts.is_empty() && ambiguous.is_empty() {
+                    println!("No inconsistencies found.");
+                }
 
-                if name.is_empty() {
-                    println!("Error: Name cannot be empty!");
+                if !ghosts.is_empty() {
+                    for (user, book_id) in &ghosts {
+                        println!(
+                            "User '{}' lists book ID {} as borrowed, but no copy of it is on loan.",
+                            user, book_id
+                        );
+                    }
+
+                    println!("Remove these stale entries? (y/n): ");
+                    let mut answer = String::new();
+                    io::stdin()
+                        .read_line(&mut answer)
+                        .expect("Failed to read answer");
+                    if answer.trim().eq_ignore_ascii_case("y") {
+                        let removed = library.repair_ghost_holds();
+                        println!("Removed {} stale entries.", removed);
+                    }
+                }
+
+                // Only the operator can tell which of these users really has a copy
+                for (book_id, holders) in ambiguous {
+                    let copies_out = library.book(book_id).map_or(0, |b| b.copies_out());
+                    println!(
+                        "\nBook ID {} has {} copies on loan but is listed by {} users:",
+                        book_id,
+                        copies_out,
+                        holders.len()
+                    );
+                    for (number, user) in holders.iter().enumerate() {
+                        println!("{}. {}", number + 1, user);
+                    }
+
+                    println!("Enter the number of a user who does not have a copy (blank to skip): ");
+                    let mut answer = String::new();
+                    io::stdin()
+                        .read_line(&mut answer)
+                        .expect("Failed to read answer");
+                    match answer.trim() {
+                        "" => {}
+                        answer => match answer.parse::<usize>().ok().and_then(|n| holders.get(n.wrapping_sub(1))) {
+                            Some(user) => match library.remove_stale_hold(user, book_id) {
+                                Ok(()) => println!("Removed book ID {} from user '{}'.", book_id, user),
+                                Err(e) => println!("Error: {}", e),
+                            },
+                            None => println!("Invalid choice! Skipping book ID {}.", book_id),
+                        },
+                    }
+                }
+            }
+            7 | 8 => {
+                let Some(name) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "Name") else {
+                    continue;
+                };
+
+                if choice == 7 {
+                    match library.suspend_user(&name) {
+                        Ok(()) => println!("User '{}' suspended", name),
+                        Err(e) => println!("Error: {}", e),
+                    }
                 } else {
-                    library.add_user(name);
+                    match library.reinstate_user(&name) {
+                        Ok(()) => println!("User '{}' reinstated", name),
+                        Err(e) => println!("Error: {}", e),
+                    }
                 }
             }
-            3 => {
-                println!("Enter book title to issue: ");
-                let mut title = String::new();
+            9 => {
+                let Some(user) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "User name") else {
+                    continue;
+                };
+
+                println!("Enter ID of the copy the user holds: ");
+                let mut from_id = String::new();
                 io::stdin()
-                    .read_line(&mut title)
-                    .expect("Failed to read title");
-                let title = title.trim().to_string();
+                    .read_line(&mut from_id)
+                    .expect("Failed to read ID");
 
-                println!("Enter user name: ");
-                let mut user = String::new();
+                println!("Enter ID of the copy to lend instead: ");
+                let mut to_id = String::new();
                 io::stdin()
-                    .read_line(&mut user)
-                    .expect("Failed to read user");
-                let user = user.trim();
+                    .read_line(&mut to_id)
+                    .expect("Failed to read ID");
+
+                match (from_id.trim().parse::<u32>(), to_id.trim().parse::<u32>()) {
+                    (Ok(from_id), Ok(to_id)) => match library.swap_copy(&user, from_id, to_id) {
+                        Ok(()) => println!(
+                            "User '{}' now holds book {} instead of book {}",
+                            user, to_id, from_id
+                        ),
+                        Err(e) => println!("Error: {}", e),
+                    },
+                    _ => println!("Invalid input! Book IDs must be numbers."),
+                }
+            }
+            10 => {
+                let Some(rating) = prompt_required(&mut io::stdin().lock(), "Enter age rating: ", "Age rating") else {
+                    continue;
+                };
 
-                if title.is_empty() || user.is_empty() {
-                    println!("Error: Title and user name cannot be empty!");
+                let books = library.books_for_age(&rating);
+                if books.is_empty() {
+                    println!("No books rated '{}'.", rating);
                 } else {
-                    library.issue_book(title, user);
+                    println!("\nBooks rated '{}':", rating);
+                    for book in books {
+                        print_book(book);
+                    }
                 }
             }
-            4 => {
-                println!("Enter book title to return: ");
+            11 => {
+                let Some(query) = prompt_required(&mut io::stdin().lock(), "Enter search words: ", "Search") else {
+                    continue;
+                };
+                display_query_results(&library.index_search(&query));
+            }
+            12 => {
+                let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter book ID: ") else {
+                    continue;
+                };
+
+                match library.book(id) {
+                    Some(book) => print_book_details(book),
+                    None => println!("No book found with ID {}.", id),
+                }
+            }
+            13 => {
+                let report = library.acquisitions_by_operator();
+                if report.is_empty() {
+                    println!("No books available.");
+                }
+                for (operator, books) in report {
+                    println!("\nAdded by {} ({} books):", operator.unwrap_or("(not recorded)"), books.len());
+                    for book in books {
+                        print_book(book);
+                    }
+                }
+            }
+            14 => {
+                println!("Delete all demo books and users? (y/n): ");
+                let mut answer = String::new();
+                io::stdin()
+                    .read_line(&mut answer)
+                    .expect("Failed to read answer");
+                if answer.trim().eq_ignore_ascii_case("y") {
+                    let (books, users) = library.purge_demo();
+                    println!("Removed {} demo books and {} demo users.", books, users);
+                }
+            }
+            15 => {
+                if let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter ID of the book to remove: ") {
+                    match library.remove_book(id) {
+                        Ok(book) => println!("Book '{}' by '{}' removed", book.title, book.author),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+            }
+            16 => {
+                let today = Date::today();
+                let overdue = library.overdue_books(today);
+                if overdue.is_empty() {
+                    println!("No overdue books.");
+                } else {
+                    println!("\nOverdue Books (as of {}):", today);
+                    for (user, book_id, due_date) in overdue {
+                        let title = library
+                            .book(book_id)
+                            .map_or("(unknown book)", |b| b.title.as_str());
+                        println!(
+                            "User: {}, Book ID: {}, Title: {}, Due: {} ({} days late)",
+                            user,
+                            book_id,
+                            title,
+                            due_date,
+                            due_date.days_until(today)
+                        );
+                    }
+                }
+            }
+            17 => {
+                println!("Current borrowing limit: {} books per user", library.max_borrowed());
+                println!("Enter new limit (blank to keep): ");
+                let mut limit = String::new();
+                io::stdin()
+                    .read_line(&mut limit)
+                    .expect("Failed to read limit");
+
+                match limit.trim() {
+                    "" => {}
+                    limit => match limit.parse::<u32>() {
+                        Ok(limit) => {
+                            library.set_max_borrowed(limit);
+                            println!("Borrowing limit set to {}", limit);
+                        }
+                        Err(_) => println!("Invalid input! Limit must be a number."),
+                    },
+                }
+            }
+            18 => {
+                let Some(query) = prompt_required(&mut io::stdin().lock(), "Enter search term: ", "Search term") else {
+                    continue;
+                };
+                match library.search_books(&query) {
+                    Ok(books) => display_query_results(&books),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            19 => display_users(&library),
+            20 => display_stats(&library),
+            21 => {
+                let Some(path) = prompt_required(&mut io::stdin().lock(), "Enter CSV filename: ", "Filename") else {
+                    continue;
+                };
+                match library.export_books_csv(&path) {
+                    Ok(()) => println!("Books exported to {}", path),
+                    Err(e) => println!("Error exporting books: {}", e),
+                }
+            }
+            22 => {
+                let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter ID of the book to edit: ") else {
+                    continue;
+                };
+                let Some(book) = library.book(id) else {
+                    println!("No book found with ID {}.", id);
+                    continue;
+                };
+
+                println!("Enter new title (blank to keep '{}'): ", book.title);
                 let mut title = String::new();
                 io::stdin()
                     .read_line(&mut title)
                     .expect("Failed to read title");
-                let title = title.trim().to_string();
 
-                println!("Enter user name: ");
-                let mut user = String::new();
+                println!("Enter new author (blank to keep '{}'): ", book.author);
+                let mut author = String::new();
                 io::stdin()
-                    .read_line(&mut user)
-                    .expect("Failed to read user");
-                let user = user.trim();
+                    .read_line(&mut author)
+                    .expect("Failed to read author");
 
-                if title.is_empty() || user.is_empty() {
-                    println!("Error: Title and user name cannot be empty!");
+                let title = Some(title.trim().to_string()).filter(|t| !t.is_empty());
+                let author = Some(author.trim().to_string()).filter(|a| !a.is_empty());
+                if title.is_none() && author.is_none() {
+                    println!("Nothing changed.");
                 } else {
-                    library.return_book(title, user);
+                    match library.edit_book(id, title, author) {
+                        Ok(()) => {
+                            if let Some(book) = library.book(id) {
+                                println!("Book {} updated: '{}' by '{}'", id, book.title, book.author);
+                            }
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
                 }
             }
-            5 => library.display_books(),
-            6 => {
-                match library.save_to_file("library.json") {
-                    Ok(()) => println!("Data saved to library.json"),
+            23 => {
+                let Some(user) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "User name") else {
+                    continue;
+                };
+                let ReturnedBooks { returned, unknown } = match library.return_all_for_user(&user) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                };
+                if returned.is_empty() && unknown.is_empty() {
+                    println!("User '{}' had no books to return.", user);
+                }
+                if !returned.is_empty() {
+                    println!("Returned {} books for user '{}':", returned.len(), user);
+                    for (title, passed_to) in returned {
+                        match passed_to {
+                            Some(next) => println!("  {} (now issued to '{}', who had reserved it)", title, next),
+                            None => println!("  {}", title),
+                        }
+                    }
+                }
+                if !unknown.is_empty() {
+                    println!("Cleared {} loans of books that no longer exist:", unknown.len());
+                    for id in unknown {
+                        println!("  book #{}", id);
+                    }
+                }
+            }
+            24 => {
+                println!("How many recent entries? (blank for 20): ");
+                let mut count = String::new();
+                io::stdin()
+                    .read_line(&mut count)
+                    .expect("Failed to read count");
+                let count = match count.trim() {
+                    "" => 20,
+                    count => match count.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            println!("Invalid input! Count must be a number.");
+                            continue;
+                        }
+                    },
+                };
+
+                match library.recent_log_entries(count) {
+                    Ok(entries) if entries.is_empty() => println!("The audit log is empty."),
+                    Ok(entries) => {
+                        for entry in entries {
+                            println!("{}", entry);
+                        }
+                    }
+                    Err(e) => println!("Error reading audit log: {}", e),
+                }
+            }
+            25 => {
+                let Some(id) = prompt_book_id(&mut io::stdin().lock(), "Enter ID of the book to reserve: ") else {
+                    continue;
+                };
+                let Some(user) = prompt_required(&mut io::stdin().lock(), "Enter user name: ", "User name") else {
+                    continue;
+                };
+
+                match library.reserve_book(id, &user) {
+                    Ok(position) => {
+                        let title = library.book(id).map_or("", |b| b.title.as_str());
+                        println!(
+                            "User '{}' reserved '{}' (position {} in the queue)",
+                            user, title, position
+                        );
+                    }
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+            0 => {
+                match library.save_to_file(&data_file) {
+                    Ok(()) => println!("Data saved to {}", data_file),
                     Err(e) => eprintln!("Error saving data: {}", e),
                 }
                 println!("Exiting...");
                 break;
             }
-            _ => println!("Invalid choice! Please select 1–6."),
+            _ => println!("Invalid choice! Please select 0–25."),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn prompt_required_reprompts_after_blank_input() {
+        let mut input = Cursor::new("\n  Dune  \n");
+        assert_eq!(prompt_required(&mut input, "Title: ", "Title"), Some("Dune".to_string()));
+    }
+
+    #[test]
+    fn prompt_required_cancels_after_whitespace_only_attempts() {
+        let mut input = Cursor::new(" \n\t\n   \nDune\n");
+        assert_eq!(prompt_required(&mut input, "Title: ", "Title"), None);
+    }
+
+    #[test]
+    fn prompt_required_cancels_at_end_of_input() {
+        let mut input = Cursor::new("");
+        assert_eq!(prompt_required(&mut input, "Title: ", "Title"), None);
+
+        let mut input = Cursor::new("\n");
+        assert_eq!(prompt_required(&mut input, "Title: ", "Title"), None);
+    }
+
+    #[test]
+    fn audit_log_path_never_matches_the_data_file() {
+        assert_eq!(audit_log_path("library.json"), "library.log");
+        assert_eq!(audit_log_path("shared/library.json"), "shared/library.log");
+        assert_eq!(audit_log_path("library"), "library.log");
+        assert_eq!(audit_log_path("library.log"), "library.log.log");
+    }
+}